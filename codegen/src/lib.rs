@@ -3,162 +3,289 @@ extern crate proc_macro;
 use proc_macro::{TokenStream};
 
 use syn::{DataStruct, Fields, Meta, LitInt, parse_macro_input, DeriveInput};
-use quote::{quote, quote_spanned};
+use quote::{quote, quote_spanned, ToTokens};
 
 use darling::FromMeta;
 use syn::spanned::Spanned;
+use syn::ext::IdentExt;
 use syn::export::TokenStream2;
 
+mod schema;
+mod wire_enum;
+
 #[derive(Debug, darling::FromMeta)]
-struct Wrapper {
-    #[darling(default)]
-    name: String,
-    #[darling(default)]
-    since: Option<usize>,
-    #[darling(default)]
-    until: Option<usize>,
-    #[darling(default)]
-    default: bool,
+struct CompactArgs {
+    /// First version in which the struct uses the compact encoding.
+    since: usize,
 }
 
-#[derive(Debug, darling::FromMeta)]
-struct StructArgs {}
+#[derive(Debug, Default, darling::FromMeta)]
+struct StructArgs {
+    /// Makes the struct pick the encoding by version, regardless of the encoding requested by its parent.
+    #[darling(default)]
+    compact: Option<CompactArgs>,
+}
 
-#[derive(Debug, darling::FromMeta)]
+#[derive(Debug, Default, darling::FromMeta)]
 struct FieldArgs {
     #[darling(default)]
     since: Option<usize>,
     #[darling(default)]
     until: Option<usize>,
+    /// Value encoded for a versioned field left `None` in a version it is present in, `Default::default()` otherwise.
+    #[darling(default)]
+    default: Option<String>,
+
+    /// Tag of a tagged field, the value is stored in the tag buffer of the struct.
+    #[darling(default)]
+    tag: Option<u32>,
+    /// First version in which the tagged field is present.
     #[darling(default)]
     tag_since: Option<usize>,
 
+    /// Marks the tag buffer of the struct, which holds tagged fields.
     #[darling(default)]
     tags: bool,
 }
 
-fn args_from_attr<T: FromMeta>(attr: &syn::Attribute) -> Option<T> {
-    if attr.path.get_ident().unwrap() == "wired" {
-        let data: Meta = attr.parse_meta().expect("wired attribute args");
-        let args = T::from_meta(&data).expect("`wired` attr arguments");
-        Some(args)
-    } else {
-        None
+/// Parses the last `wired` attribute, unknown or malformed arguments are reported as errors.
+fn args_from_attrs<T: FromMeta + Default>(attrs: &[syn::Attribute]) -> Result<T, darling::Error> {
+    let mut res = None;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("wired")) {
+        let data: Meta = attr.parse_meta().map_err(|e| darling::Error::custom(e).with_span(attr))?;
+        res = Some(T::from_meta(&data).map_err(|e| e.with_span(&data))?);
     }
+    Ok(res.unwrap_or_default())
 }
 
-
-fn de(wrap: TokenStream2) -> (TokenStream2, TokenStream2) {
-    let span = wrap.span().clone();
-    (
-        quote_spanned! { span => crate::proto::Wired::from_wire(wire)? },
-        quote_spanned! { span => crate::proto::Wired::from_wire_compact(wire)? }
-    )
+fn lit(v: impl ToString, span: proc_macro2::Span) -> LitInt {
+    LitInt::new(&v.to_string(), span)
 }
 
-
-fn ser(get: TokenStream2) -> (TokenStream2, TokenStream2) {
-    let span = get.span().clone();
-    (
-        quote_spanned! { span => crate::proto::Wired::to_wire(#get, wire); },
-        quote_spanned! { span => crate::proto::Wired::to_wire_compact(#get, wire); }
-    )
+/// Condition on `wire.version` under which the field is present.
+fn version_cond(args: &FieldArgs, span: proc_macro2::Span) -> Option<TokenStream2> {
+    match (args.since, args.until) {
+        (Some(since), None) => {
+            let since = lit(since, span);
+            Some(quote_spanned! { span => wire.version >= #since })
+        }
+        (None, Some(until)) => {
+            let until = lit(until, span);
+            Some(quote_spanned! { span => wire.version <= #until })
+        }
+        (Some(since), Some(until)) if since == until => {
+            let since = lit(since, span);
+            Some(quote_spanned! { span => wire.version == #since })
+        }
+        (Some(since), Some(until)) => {
+            let since = lit(since, span);
+            let until = lit(until, span);
+            Some(quote_spanned! { span => wire.version >= #since && wire.version <= #until })
+        }
+        (None, None) => None,
+    }
 }
 
-fn opt_to_from(cond: &TokenStream2, to: &TokenStream2, from: &TokenStream2) -> (TokenStream2, TokenStream2) {
-    let span = cond.span().clone();
-
-    (
-        quote_spanned! { span => if #cond { #to } },
-        quote_spanned! { span => if #cond { Some(#from) } else { None }}
-    )
+struct Generated {
+    to: Vec<TokenStream2>,
+    to_compact: Vec<TokenStream2>,
+    from: Vec<TokenStream2>,
+    from_compact: Vec<TokenStream2>,
 }
 
-#[proc_macro_derive(Wired, attributes(wired))]
-pub fn derive_wired(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
+fn derive_struct(fields: &syn::FieldsNamed) -> Result<Generated, darling::Error> {
+    let mut errors = vec![];
+    let fields = fields.named.iter().filter_map(|f| {
+        match args_from_attrs::<FieldArgs>(&f.attrs) {
+            Ok(args) => Some((f.ident.clone().unwrap(), &f.ty, args)),
+            Err(e) => {
+                errors.push(e);
+                None
+            }
+        }
+    }).collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(darling::Error::multiple(errors));
+    }
 
-    let ident = input.ident;
-    let (to, from, to_compact, from_compact) = match input.data {
-        syn::Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) => {
-            // TODO: Use for specifying tagged_since
-            let mut _args = input.attrs.iter().filter_map(args_from_attr::<StructArgs>);
+    let tagged = fields.iter().filter(|(_, _, args)| args.tag.is_some()).collect::<Vec<_>>();
+    let tags_field = fields.iter().find(|(_, _, args)| args.tags);
 
-            let versions = fields.named.iter().map(|f| {
-                let vals = f.attrs.iter().filter_map(args_from_attr::<FieldArgs>);
-                if let Some(v) = vals.last() {
-                    return Some((v.since, v.until));
-                }
-                return None;
-            });
+    if !tagged.is_empty() && tags_field.is_none() {
+        let (name, _, _) = tagged[0];
+        return Err(darling::Error::custom("Tagged fields require a field marked with `#[wired(tags)]`").with_span(name));
+    }
 
-            let names = fields.named.iter().map(|f| {
-                let name = &f.ident;
-                quote_spanned! {f.ident.span() => #name}
-            });
+    let mut res = Generated { to: vec![], to_compact: vec![], from: vec![], from_compact: vec![] };
 
-            let (names, n1): (Vec<_>, Vec<_>) = names.map(|n| (n.clone(), n)).unzip();
+    for (name, ty, args) in &fields {
+        let span = name.span();
+        if args.tag.is_some() {
+            // Written as part of the tag buffer
+            continue;
+        }
+        let cond = version_cond(args, span);
+        let path = syn::LitStr::new(&name.unraw().to_string(), span);
+        let ctx = quote_spanned! { span => .map_err(|e| e.in_field(#path))? };
+
+        if args.tags {
+            // Merge typed tagged fields into the buffer of unknown tags
+            let base = match cond {
+                Some(_) => quote_spanned! { span => self.#name.clone().unwrap_or_default() },
+                None => quote_spanned! { span => self.#name.clone() },
+            };
+            let puts = tagged.iter().map(|(field, _, args)| {
+                let tag = lit(args.tag.unwrap(), field.span());
+                let since = lit(args.tag_since.unwrap_or(0), field.span());
+                quote_spanned! { field.span() =>
+                    if wire.version >= #since {
+                        if let Some(v) = &self.#field {
+                            tags.put(#tag, v, wire.version);
+                        }
+                    }
+                }
+            }).collect::<Vec<_>>();
+            let merged = quote_spanned! { span =>
+                #[allow(unused_mut)]
+                let mut tags = #base;
+                #(#puts)*
+            };
+            match &cond {
+                Some(cond) => {
+                    res.to.push(quote_spanned! { span => if #cond { #merged crate::proto::Wired::to_wire(&tags, wire); } });
+                    res.to_compact.push(quote_spanned! { span => if #cond { #merged crate::proto::Wired::to_wire_compact(&tags, wire); } });
+                }
+                None => {
+                    res.to.push(quote_spanned! { span => { #merged crate::proto::Wired::to_wire(&tags, wire); } });
+                    res.to_compact.push(quote_spanned! { span => { #merged crate::proto::Wired::to_wire_compact(&tags, wire); } });
+                }
+            }
+        } else {
+            match &cond {
+                Some(cond) => {
+                    let default = match &args.default {
+                        Some(default) => syn::parse_str::<syn::Expr>(default)
+                            .map_err(|e| darling::Error::custom(e).with_span(name))?
+                            .into_token_stream(),
+                        None => quote_spanned! { span => Default::default() },
+                    };
+                    let value = quote_spanned! { span =>
+                        let default;
+                        let value = match &self.#name {
+                            Some(value) => value,
+                            None => {
+                                default = #default;
+                                &default
+                            }
+                        };
+                    };
+                    res.to.push(quote_spanned! { span => if #cond { #value crate::proto::Wired::to_wire(value, wire); } });
+                    res.to_compact.push(quote_spanned! { span => if #cond { #value crate::proto::Wired::to_wire_compact(value, wire); } });
+                }
+                None => {
+                    res.to.push(quote_spanned! { span => crate::proto::Wired::to_wire(&self.#name, wire); });
+                    res.to_compact.push(quote_spanned! { span => crate::proto::Wired::to_wire_compact(&self.#name, wire); });
+                }
+            }
+        }
 
-            let merged = versions.zip(n1).map(|(ver, name)| {
-                let to_from: (TokenStream2, TokenStream2);
-                let to_from_compact: (TokenStream2, TokenStream2);
+        match &cond {
+            Some(cond) => {
+                res.from.push(quote_spanned! { span =>
+                    #[allow(unused_mut)]
+                    let mut #name: #ty = if #cond { Some(crate::proto::Wired::from_wire(wire)#ctx) } else { None };
+                });
+                res.from_compact.push(quote_spanned! { span =>
+                    #[allow(unused_mut)]
+                    let mut #name: #ty = if #cond { Some(crate::proto::Wired::from_wire_compact(wire)#ctx) } else { None };
+                });
+            }
+            None => {
+                res.from.push(quote_spanned! { span =>
+                    #[allow(unused_mut)]
+                    let mut #name: #ty = crate::proto::Wired::from_wire(wire)#ctx;
+                });
+                res.from_compact.push(quote_spanned! { span =>
+                    #[allow(unused_mut)]
+                    let mut #name: #ty = crate::proto::Wired::from_wire_compact(wire)#ctx;
+                });
+            }
+        }
+    }
 
-                let (to_opt, to_opt_compact) = ser(quote_spanned! { name.span() => self.#name.as_ref().expect("VER")});
-                let (from_opt, from_opt_compact) = de(quote_spanned! { name.span() => Some });
+    if let Some((tags_name, _, tags_args)) = tags_field {
+        let buffer = match version_cond(tags_args, tags_name.span()) {
+            Some(_) => quote_spanned! { tags_name.span() => #tags_name.as_mut() },
+            None => quote_spanned! { tags_name.span() => Some(&mut #tags_name) },
+        };
+        for (field, ty, args) in &tagged {
+            let tag = lit(args.tag.unwrap(), field.span());
+            let since = lit(args.tag_since.unwrap_or(0), field.span());
+            let path = syn::LitStr::new(&field.unraw().to_string(), field.span());
+            let take = quote_spanned! { field.span() =>
+                let #field: #ty = match #buffer {
+                    Some(tags) if wire.version >= #since => tags.take(#tag, wire.version).map_err(|e| e.in_field(#path))?,
+                    _ => None,
+                };
+            };
+            res.from.push(take.clone());
+            res.from_compact.push(take);
+        }
+    }
 
-                match ver {
-                    Some((Some(since), None)) => {
-                        let since = LitInt::new(&since.to_string(), name.span());
-                        let cond = quote_spanned! { name.span() => wire.version >= #since };
-                        to_from = opt_to_from(&cond, &to_opt, &from_opt);
-                        to_from_compact = opt_to_from(&cond, &to_opt_compact, &from_opt_compact);
-                    }
-                    Some((None, Some(until))) => {
-                        let until = LitInt::new(&until.to_string(), name.span());
-                        let cond = quote_spanned! { name.span() => wire.version <= #until };
-                        to_from = opt_to_from(&cond, &to_opt, &from_opt);
-                        to_from_compact = opt_to_from(&cond, &to_opt_compact, &from_opt_compact);
-                    }
-                    Some((Some(since), Some(until))) => {
-                        let since = LitInt::new(&since.to_string(), name.span());
-                        let until = LitInt::new(&until.to_string(), name.span());
-                        let cond = quote_spanned! { name.span() => wire.version >= #since && wire.version <= #until };
+    Ok(res)
+}
 
-                        to_from = opt_to_from(&cond, &to_opt, &from_opt);
-                        to_from_compact = opt_to_from(&cond, &to_opt_compact, &from_opt_compact);
-                    }
-                    Some((None, None)) | None => {
-                        to_from = (
-                            quote_spanned! {name.span() => self.#name.to_wire(wire); },
-                            quote_spanned! {name.span() => crate::proto::Wired::from_wire(wire)?}
-                        );
-                        to_from_compact = (
-                            quote_spanned! {name.span() => self.#name.to_wire_compact(wire); },
-                            quote_spanned! {name.span() => crate::proto::Wired::from_wire_compact(wire)?}
-                        );
-                    }
-                }
-                (to_from, to_from_compact)
-            });
+#[proc_macro_derive(Wired, attributes(wired))]
+pub fn derive_wired(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
 
-            let (to_from, to_from_compact): (Vec<_>, Vec<_>) = merged.unzip();
+    let ident = &input.ident;
+    let args = match args_from_attrs::<StructArgs>(&input.attrs) {
+        Ok(args) => args,
+        Err(e) => return e.write_errors().into(),
+    };
 
-            let (to, from): (Vec<_>, Vec<_>) = to_from.into_iter().unzip();
-            let (to_compact, from_compact): (Vec<_>, Vec<_>) = to_from_compact.into_iter().unzip();
+    let (mut to, mut from, mut to_compact, mut from_compact) = match &input.data {
+        syn::Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) => {
+            let names = fields.named.iter().map(|f| {
+                let name = &f.ident;
+                quote_spanned! {f.ident.span() => #name}
+            }).collect::<Vec<_>>();
 
+            let Generated { to, to_compact, from, from_compact } = match derive_struct(fields) {
+                Ok(gen) => gen,
+                Err(e) => return e.write_errors().into(),
+            };
 
             (
                 quote! { #(#to)* },
-                quote! { Ok(Self { #(#names: #from,)* })},
+                quote! { #(#from)* Ok(Self { #(#names,)* }) },
                 quote! { #(#to_compact)* },
-                quote! { Ok(Self{#(#names: #from_compact,)*})}
+                quote! { #(#from_compact)* Ok(Self { #(#names,)* }) }
             )
         }
+        syn::Data::Enum(data) => {
+            return match wire_enum::expand(&input, data) {
+                Ok(tokens) => tokens.into(),
+                Err(e) => e.to_compile_error().into(),
+            };
+        }
         _ => {
-            panic!("Unsupported")
+            return syn::Error::new(ident.span(), "Wired can only be derived for structs with named fields and enums")
+                .to_compile_error()
+                .into();
         }
     };
 
+    if let Some(CompactArgs { since }) = args.compact {
+        let since = lit(since, proc_macro2::Span::call_site());
+        to = quote! { if wire.version >= #since { #to_compact } else { #to } };
+        from = quote! { if wire.version >= #since { #from_compact } else { #from } };
+        to_compact = to.clone();
+        from_compact = from.clone();
+    }
+
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
 
     let tokens = quote! {
@@ -181,6 +308,41 @@ pub fn derive_wired(input: TokenStream) -> TokenStream {
     tokens.into()
 }
 
+/// Generates `Request` and `Response` message structs from the vendored Kafka JSON schemas,
+/// `kafka_schema!("Heartbeat")` reads `schemas/HeartbeatRequest.json` and `schemas/HeartbeatResponse.json`.
+///
+/// Items in a block after the name are added to the `ApiRequest` impl of the request, replacing the generated
+/// items of the same name, e.g. `kafka_schema!("Produce", { const VERSIONS: (usize, usize) = (3, 8); })`.
+#[proc_macro]
+pub fn kafka_schema(input: TokenStream) -> TokenStream {
+    let SchemaArgs { api, items } = parse_macro_input!(input as SchemaArgs);
+    match schema::expand(&api.value(), &items) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => syn::Error::new(api.span(), e).to_compile_error().into(),
+    }
+}
+
+/// Name of the api, optionally followed by a block of hand-written items of its `ApiRequest` impl.
+struct SchemaArgs {
+    api: syn::LitStr,
+    items: Vec<syn::ImplItem>,
+}
+
+impl syn::parse::Parse for SchemaArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let api = input.parse()?;
+        let mut items = vec![];
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            let content;
+            syn::braced!(content in input);
+            while !content.is_empty() {
+                items.push(content.parse()?);
+            }
+        }
+        Ok(SchemaArgs { api, items })
+    }
+}
+
 /*
 #[proc_macro_derive(Wired)]
 pub fn derive_apitypes(input: TokenStream) -> TokenStream {
@@ -0,0 +1,479 @@
+//! Generation of message structs from the JSON message schemas of Apache Kafka.
+//!
+//! https://github.com/apache/kafka/tree/trunk/clients/src/main/resources/common/message
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use serde_json::Value;
+use syn::{ImplItem, LitInt};
+
+/// Parsed `versions`-style range, `None` as upper bound means unbounded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Versions {
+    since: usize,
+    until: Option<usize>,
+}
+
+impl Versions {
+    fn parse(s: &str) -> Result<Option<Versions>, String> {
+        let s = s.trim();
+        if s == "none" {
+            return Ok(None);
+        }
+        let num = |v: &str| v.trim().parse::<usize>().map_err(|_| format!("Invalid version range `{}`", s));
+        if let Some(since) = s.strip_suffix('+') {
+            return Ok(Some(Versions { since: num(since)?, until: None }));
+        }
+        if let Some((since, until)) = s.split_once('-') {
+            return Ok(Some(Versions { since: num(since)?, until: Some(num(until)?) }));
+        }
+        let v = num(s)?;
+        Ok(Some(Versions { since: v, until: Some(v) }))
+    }
+}
+
+struct Schema {
+    name: String,
+    api_key: i16,
+    valid: Versions,
+    flexible: Option<Versions>,
+    fields: Vec<Value>,
+    common: Vec<Value>,
+}
+
+/// First version of each api throttled by the client instead of the broker (KIP-219),
+/// apis added afterwards were always throttled by the client.
+const CLIENT_THROTTLE: &[(&str, usize)] = &[
+    ("ApiVersions", 2),
+    ("CreatePartitions", 1),
+    ("DescribeGroups", 2),
+    ("Fetch", 8),
+    ("FindCoordinator", 2),
+    ("Heartbeat", 2),
+    ("JoinGroup", 3),
+    ("LeaveGroup", 2),
+    ("ListGroups", 2),
+    ("ListOffsets", 3),
+    ("Metadata", 6),
+    ("OffsetCommit", 4),
+    ("OffsetDelete", 0),
+    ("OffsetFetch", 4),
+    ("Produce", 6),
+    ("SyncGroup", 2),
+];
+
+/// First version of `api` throttled by the client, apis with a throttle time must be listed in [CLIENT_THROTTLE].
+fn client_throttle(api: &str) -> Result<usize, String> {
+    CLIENT_THROTTLE.iter().find(|(name, _)| *name == api).map(|(_, since)| *since)
+        .ok_or_else(|| format!("No client throttling version of {} in CLIENT_THROTTLE", api))
+}
+
+/// Reads a schema file, the upstream files contain full-line `//` comments which are not valid JSON.
+fn load(path: &Path) -> Result<Schema, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("Reading {}: {}", path.display(), e))?;
+    let data = data.lines()
+        .filter(|l| !l.trim_start().starts_with("//"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let json: Value = serde_json::from_str(&data).map_err(|e| format!("Parsing {}: {}", path.display(), e))?;
+
+    let str_field = |name: &str| json[name].as_str().ok_or_else(|| format!("{}: missing `{}`", path.display(), name));
+    Ok(Schema {
+        name: str_field("name")?.to_string(),
+        api_key: json["apiKey"].as_i64().ok_or_else(|| format!("{}: missing `apiKey`", path.display()))? as i16,
+        valid: Versions::parse(str_field("validVersions")?)?.ok_or("No valid versions")?,
+        flexible: Versions::parse(str_field("flexibleVersions")?)?,
+        fields: json["fields"].as_array().cloned().unwrap_or_default(),
+        common: json["commonStructs"].as_array().cloned().unwrap_or_default(),
+    })
+}
+
+fn snake_case(name: &str) -> String {
+    let mut res = String::new();
+    let chars = name.chars().collect::<Vec<_>>();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let prev_lower = i > 0 && chars[i - 1].is_lowercase();
+            let next_lower = chars.get(i + 1).map(|c| c.is_lowercase()).unwrap_or(false);
+            if i > 0 && (prev_lower || (next_lower && chars[i - 1].is_uppercase())) {
+                res.push('_');
+            }
+            res.extend(c.to_lowercase());
+        } else {
+            res.push(*c);
+        }
+    }
+    match res.as_str() {
+        "type" | "match" | "ref" | "mod" => format!("r#{}", res),
+        _ => res,
+    }
+}
+
+fn lit(v: usize) -> LitInt {
+    LitInt::new(&v.to_string(), Span::call_site())
+}
+
+struct Generator {
+    flexible: Option<Versions>,
+    structs: Vec<TokenStream2>,
+    /// Common structs referenced by name only, resolved when first used.
+    common: HashMap<String, Value>,
+}
+
+impl Generator {
+    fn primitive(ty: &str) -> Option<TokenStream2> {
+        Some(match ty {
+            "bool" => quote!(bool),
+            "int8" => quote!(i8),
+            "int16" => quote!(i16),
+            "uint16" => quote!(u16),
+            "int32" => quote!(i32),
+            "int64" => quote!(i64),
+            "float64" => quote!(f64),
+            "string" => quote!(String),
+            "bytes" => quote!(bytes::Bytes),
+            "records" => quote!(crate::proto::RecordSet),
+            "uuid" => quote!(crate::proto::Uuid),
+            _ => return None,
+        })
+    }
+
+    /// Type of the field value, without the wrapping `Option` for versioned fields.
+    fn field_type(&mut self, field: &Value) -> Result<TokenStream2, String> {
+        let ty = field["type"].as_str().ok_or("Field without type")?;
+        let nullable = field["nullableVersions"].as_str().map(Versions::parse).transpose()?.flatten().is_some();
+
+        let (array, inner) = match ty.strip_prefix("[]") {
+            Some(inner) => (true, inner),
+            None => (false, ty),
+        };
+        let primitive = Self::primitive(inner);
+        let is_struct = primitive.is_none();
+        let inner = match primitive {
+            Some(t) => t,
+            None => {
+                let fields = match field["fields"].as_array() {
+                    Some(fields) => fields.clone(),
+                    None => self.common.remove(inner)
+                        .map(|c| c["fields"].as_array().cloned().unwrap_or_default())
+                        .unwrap_or_default(),
+                };
+                // Common structs are emitted only once, later references have neither fields nor an entry
+                if !fields.is_empty() || field["fields"].is_array() {
+                    self.gen_struct(inner, &fields)?;
+                }
+                let ident = format_ident!("{}", inner);
+                quote!(#ident)
+            }
+        };
+
+        Ok(match (array, nullable) {
+            // Would need a presence marker, no schema of the supported apis has one
+            (false, true) if is_struct => {
+                return Err(format!("Nullable struct field `{}` is not supported", field["name"].as_str().unwrap_or(ty)));
+            }
+            (true, true) => quote!(Option<Vec<#inner>>),
+            (true, false) => quote!(Vec<#inner>),
+            (false, true) if ty == "string" || ty == "bytes" => quote!(Option<#inner>),
+            (false, _) => inner,
+        })
+    }
+
+    /// Literal of a `default` given as a string, integers may be written in hex.
+    fn literal(ty: &str, value: &str) -> Result<TokenStream2, String> {
+        let invalid = || format!("Invalid default `{}` of type `{}`", value, ty);
+        let (neg, abs) = match value.strip_prefix('-') {
+            Some(abs) => (true, abs),
+            None => (false, value),
+        };
+        let abs = match ty {
+            "bool" if value == "true" => return Ok(quote!(true)),
+            "bool" if value == "false" => return Ok(quote!(false)),
+            "string" => return Ok(quote!(String::from(#value))),
+            "int8" | "int16" | "uint16" | "int32" | "int64" => {
+                let abs = match abs.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => abs.parse(),
+                };
+                let lit = LitInt::new(&abs.map_err(|_| invalid())?.to_string(), Span::call_site());
+                quote!(#lit)
+            }
+            "float64" => {
+                let abs = abs.parse::<f64>().map_err(|_| invalid())?;
+                let lit = syn::LitFloat::new(&format!("{:?}", abs), Span::call_site());
+                quote!(#lit)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(if neg { quote!(-#abs) } else { abs })
+    }
+
+    /// Value of a field without the wrapping `Option` for versioned fields, `None` if it is the default of its type.
+    ///
+    /// Like upstream, nullable fields are null by default only when their `default` is `"null"`.
+    fn default_value(field: &Value) -> Result<Option<TokenStream2>, String> {
+        let ty = field["type"].as_str().ok_or("Field without type")?;
+        let nullable = field["nullableVersions"].as_str().map(Versions::parse).transpose()?.flatten().is_some();
+        let optional = nullable && (ty.starts_with("[]") || ty == "string" || ty == "bytes");
+
+        let value = match &field["default"] {
+            Value::Null => None,
+            Value::String(value) if value == "null" && optional => return Ok(Some(quote!(None))),
+            Value::String(value) => Some(Self::literal(ty, value)?),
+            other => Some(Self::literal(ty, &other.to_string())?),
+        };
+        Ok(match (optional, value) {
+            (true, Some(value)) => Some(quote!(Some(#value))),
+            (true, None) => Some(quote!(Some(Default::default()))),
+            (false, value) => value,
+        })
+    }
+
+    fn gen_struct(&mut self, name: &str, fields: &[Value]) -> Result<(), String> {
+        let ident = format_ident!("{}", name);
+        let mut defs = vec![];
+        // Values of the fields in `Default::default()`, versioned fields are set so that the default encodes at any version
+        let mut defaults = vec![];
+        let mut derive_default = true;
+
+        for field in fields {
+            let fname = field["name"].as_str().ok_or("Field without name")?;
+            let fident = syn::parse_str::<syn::Ident>(&snake_case(fname)).map_err(|e| e.to_string())?;
+            let ty = self.field_type(field)?;
+            let about = field["about"].as_str().unwrap_or("").to_string();
+            let doc = if about.is_empty() { quote!() } else { quote!(#[doc = #about]) };
+
+            let versions = Versions::parse(field["versions"].as_str().ok_or("Field without versions")?)?;
+            let tagged = field["taggedVersions"].as_str().map(Versions::parse).transpose()?.flatten();
+
+            if let Some(tagged) = tagged {
+                let tag = lit(field["tag"].as_u64().ok_or("Tagged field without tag")? as usize);
+                let since = lit(tagged.since);
+                defs.push(quote! {
+                    #doc
+                    #[wired(tag = #tag, tag_since = #since)]
+                    pub #fident: Option<#ty>,
+                });
+                defaults.push(quote!(#fident: None,));
+                continue;
+            }
+
+            let value = Self::default_value(field)?;
+            match versions {
+                Some(Versions { since: 0, until: None }) => {
+                    defs.push(quote! {
+                        #doc
+                        pub #fident: #ty,
+                    });
+                    derive_default &= value.is_none();
+                    let value = value.unwrap_or_else(|| quote!(Default::default()));
+                    defaults.push(quote!(#fident: #value,));
+                }
+                Some(Versions { since, until }) => {
+                    let since = if since > 0 { let v = lit(since); quote!(since = #v,) } else { quote!() };
+                    let until = until.map(|u| { let v = lit(u); quote!(until = #v,) }).unwrap_or_default();
+                    // Encoded when the field is left `None`
+                    let default = value.as_ref().map(|v| { let v = v.to_string(); quote!(default = #v,) }).unwrap_or_default();
+                    defs.push(quote! {
+                        #doc
+                        #[wired(#since #until #default)]
+                        pub #fident: Option<#ty>,
+                    });
+                    derive_default = false;
+                    let value = value.unwrap_or_else(|| quote!(Default::default()));
+                    defaults.push(quote!(#fident: Some(#value),));
+                }
+                None => {}
+            }
+        }
+
+        let compact = match self.flexible {
+            Some(flex) => {
+                let since = lit(flex.since);
+                defs.push(quote! {
+                    #[wired(since = #since, tags)]
+                    pub tags: Option<crate::proto::TagBuffer>,
+                });
+                defaults.push(quote!(tags: None,));
+                quote!(#[wired(compact(since = #since))])
+            }
+            None => quote!(),
+        };
+
+        if derive_default {
+            self.structs.push(quote! {
+                #[derive(Debug, Clone, Default, Wired)]
+                #compact
+                pub struct #ident {
+                    #(#defs)*
+                }
+            });
+        } else {
+            self.structs.push(quote! {
+                #[derive(Debug, Clone, Wired)]
+                #compact
+                pub struct #ident {
+                    #(#defs)*
+                }
+
+                impl Default for #ident {
+                    fn default() -> Self {
+                        #ident {
+                            #(#defaults)*
+                        }
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn generate(path: &Path, as_name: &str) -> Result<(Schema, Vec<TokenStream2>), String> {
+    let schema = load(path)?;
+    let mut gen = Generator {
+        flexible: schema.flexible,
+        structs: vec![],
+        common: schema.common.iter()
+            .filter_map(|c| Some((c["name"].as_str()?.to_string(), c.clone())))
+            .collect(),
+    };
+    gen.gen_struct(as_name, &schema.fields)?;
+    Ok((schema, gen.structs))
+}
+
+/// Name of an item of an impl block, used to let hand-written items replace the generated ones.
+fn item_name(item: &ImplItem) -> Option<String> {
+    Some(match item {
+        ImplItem::Const(c) => c.ident.to_string(),
+        ImplItem::Method(m) => m.sig.ident.to_string(),
+        ImplItem::Type(t) => t.ident.to_string(),
+        _ => return None,
+    })
+}
+
+/// Expands `kafka_schema!("Heartbeat")` into `Request` and `Response` structs generated from
+/// `schemas/HeartbeatRequest.json` and `schemas/HeartbeatResponse.json`.
+///
+/// `items` are added to the `ApiRequest` impl of the request, replacing the generated items of the same name.
+pub fn expand(api: &str, items: &[ImplItem]) -> Result<TokenStream2, String> {
+    let dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").map_err(|e| e.to_string())?).join("schemas");
+    let req_path = dir.join(format!("{}Request.json", api));
+    let res_path = dir.join(format!("{}Response.json", api));
+
+    let (req, req_structs) = generate(&req_path, "Request")?;
+    let (res, res_structs) = generate(&res_path, "Response")?;
+    if req.api_key != res.api_key {
+        return Err(format!("{} and {} have different api keys", req.name, res.name));
+    }
+
+    let api_name = req.name.strip_suffix("Request").unwrap_or(&req.name);
+    let api_key = format_ident!("{}", api_name);
+    let flexible = lit(req.flexible.map(|f| f.since).unwrap_or(99));
+    let min = lit(req.valid.since);
+    let max = lit(req.valid.until.unwrap_or(req.valid.since));
+    let (req_path, res_path) = (req_path.display().to_string(), res_path.display().to_string());
+
+    let throttle = match res.fields.iter().find(|f| f["name"] == "ThrottleTimeMs") {
+        Some(field) => {
+            let since = lit(client_throttle(api_name)?);
+            let value = match field["versions"].as_str() {
+                Some("0+") => quote!(Some(res.throttle_time_ms)),
+                _ => quote!(res.throttle_time_ms),
+            };
+            let item: ImplItem = syn::parse_quote! {
+                fn throttle_time(version: usize, res: &Response) -> Option<std::time::Duration> {
+                    crate::proto::client_throttle(version, #since, #value)
+                }
+            };
+            Some(item)
+        }
+        None => None,
+    };
+
+    let generated: Vec<ImplItem> = vec![
+        syn::parse_quote!(const API_KEY: crate::proto::ApiKey = crate::proto::ApiKey::#api_key;),
+        syn::parse_quote!(const FLEXIBLE_VER: usize = #flexible;),
+        // Versions defined by the schema this request was generated from
+        syn::parse_quote!(const VERSIONS: (usize, usize) = (#min, #max);),
+        syn::parse_quote!(type Response = Response;),
+    ];
+    let replaced = items.iter().filter_map(item_name).collect::<Vec<_>>();
+    let generated = generated.into_iter().chain(throttle)
+        .filter(|item| item_name(item).is_none_or(|name| !replaced.contains(&name)));
+
+    Ok(quote! {
+        // Rebuild when the schemas change
+        const _: &[u8] = include_bytes!(#req_path);
+        const _: &[u8] = include_bytes!(#res_path);
+
+        impl crate::proto::ApiRequest for Request {
+            #(#generated)*
+            #(#items)*
+        }
+
+        #(#req_structs)*
+        #(#res_structs)*
+    })
+}
+
+#[test]
+fn test_snake_case() {
+    assert_eq!(snake_case("ThrottleTimeMs"), "throttle_time_ms");
+    assert_eq!(snake_case("GroupId"), "group_id");
+    assert_eq!(snake_case("ISRNodes"), "isr_nodes");
+    assert_eq!(snake_case("Type"), "r#type");
+}
+
+#[test]
+fn test_versions() {
+    assert_eq!(Versions::parse("3+").unwrap(), Some(Versions { since: 3, until: None }));
+    assert_eq!(Versions::parse("1-2").unwrap(), Some(Versions { since: 1, until: Some(2) }));
+    assert_eq!(Versions::parse("0").unwrap(), Some(Versions { since: 0, until: Some(0) }));
+    assert_eq!(Versions::parse("none").unwrap(), None);
+}
+
+#[test]
+fn test_client_throttle() {
+    assert_eq!(client_throttle("Fetch"), Ok(8));
+    assert_eq!(client_throttle("Produce"), Ok(6));
+    assert_eq!(client_throttle("Metadata"), Ok(6));
+    assert!(client_throttle("Unknown").is_err());
+}
+
+#[test]
+fn test_nullable_struct() {
+    let mut gen = Generator { flexible: None, structs: vec![], common: HashMap::new() };
+    let field = serde_json::json!({
+        "name": "Assignment", "type": "Assignment", "versions": "0+", "nullableVersions": "1+",
+        "fields": [{ "name": "Id", "type": "int32", "versions": "0+" }],
+    });
+    assert_eq!(gen.field_type(&field).unwrap_err(), "Nullable struct field `Assignment` is not supported");
+
+    let field = serde_json::json!({ "name": "Ids", "type": "[]int32", "versions": "0+", "nullableVersions": "1+" });
+    assert_eq!(gen.field_type(&field).unwrap().to_string().replace(' ', ""), "Option<Vec<i32>>");
+}
+
+#[test]
+fn test_defaults() {
+    let default = |field: Value| Generator::default_value(&field).unwrap().map(|t| t.to_string());
+    let field = |ty: &str, default: &str| serde_json::json!({ "type": ty, "default": default });
+
+    assert_eq!(default(field("int32", "0x7fffffff")), Some("2147483647".to_string()));
+    assert_eq!(default(field("int32", "-2147483648")), Some("- 2147483648".to_string()));
+    assert_eq!(default(field("bool", "true")), Some("true".to_string()));
+    assert_eq!(default(serde_json::json!({ "type": "int64" })), None);
+
+    let nullable = |default: Option<&str>| {
+        let mut field = serde_json::json!({ "type": "string", "nullableVersions": "1+" });
+        if let Some(d) = default {
+            field["default"] = d.into();
+        }
+        field
+    };
+    assert_eq!(default(nullable(Some("null"))), Some("None".to_string()));
+    assert_eq!(default(nullable(None)), Some("Some (Default :: default ())".to_string()));
+    assert!(Generator::default_value(&field("bool", "yes")).is_err());
+}
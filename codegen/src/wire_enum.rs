@@ -0,0 +1,100 @@
+//! `#[derive(Wired)]` for fieldless `#[repr(i8)]`/`#[repr(i16)]` enums, such as api keys and error codes.
+//!
+//! Values not covered by a unit variant are decoded into the single tuple variant holding the `repr` type, like `Unknown(i16)`,
+//! instead of being transmuted into an invalid discriminant.
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{DataEnum, DeriveInput, Expr, Fields, Lit, UnOp};
+
+fn repr(input: &DeriveInput) -> Result<syn::Ident, syn::Error> {
+    for attr in input.attrs.iter().filter(|a| a.path.is_ident("repr")) {
+        if let Ok(ident) = attr.parse_args::<syn::Ident>() {
+            if ident == "i8" || ident == "i16" {
+                return Ok(ident);
+            }
+        }
+    }
+    Err(syn::Error::new(input.ident.span(), "Wired enums require `#[repr(i8)]` or `#[repr(i16)]`"))
+}
+
+/// Evaluates an explicit discriminant, only integer literals and their negations are supported.
+fn discriminant(expr: &Expr) -> Result<i64, syn::Error> {
+    match expr {
+        Expr::Lit(syn::ExprLit { lit: Lit::Int(lit), .. }) => lit.base10_parse(),
+        Expr::Unary(syn::ExprUnary { op: UnOp::Neg(_), expr, .. }) => Ok(-discriminant(expr)?),
+        Expr::Group(group) => discriminant(&group.expr),
+        _ => Err(syn::Error::new(expr.span(), "Expected an integer literal")),
+    }
+}
+
+pub fn expand(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2, syn::Error> {
+    let ident = &input.ident;
+    let repr = repr(input)?;
+
+    let mut known = vec![];
+    let mut unknown = None;
+    let mut next = 0i64;
+    for variant in &data.variants {
+        match &variant.fields {
+            Fields::Unit => {
+                let value = match &variant.discriminant {
+                    Some((_, expr)) => discriminant(expr)?,
+                    None => next,
+                };
+                next = value + 1;
+                let value = syn::LitInt::new(&value.to_string(), variant.ident.span());
+                known.push((&variant.ident, value));
+            }
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 && unknown.is_none() => {
+                let ty = &fields.unnamed[0].ty;
+                if !matches!(ty, syn::Type::Path(path) if path.path.is_ident(&repr)) {
+                    return Err(syn::Error::new(ty.span(), format!("The fallback variant must hold the `{}` representation", repr)));
+                }
+                unknown = Some(&variant.ident);
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "Wired enums may only contain unit variants and a single fallback variant, like `Unknown(i16)`",
+                ));
+            }
+        }
+    }
+    let unknown = unknown.ok_or_else(|| {
+        syn::Error::new(ident.span(), "Wired enums require a fallback variant for unknown values, like `Unknown(i16)`")
+    })?;
+
+    let from = known.iter().map(|(name, value)| quote_spanned! { name.span() => #value => #ident::#name, });
+    let to = known.iter().map(|(name, value)| quote_spanned! { name.span() => #ident::#name => #value, });
+
+    Ok(quote! {
+        impl From<#repr> for #ident {
+            fn from(v: #repr) -> Self {
+                match v {
+                    #(#from)*
+                    other => #ident::#unknown(other as _),
+                }
+            }
+        }
+
+        impl From<#ident> for #repr {
+            fn from(v: #ident) -> Self {
+                match v {
+                    #(#to)*
+                    #ident::#unknown(other) => other as _,
+                }
+            }
+        }
+
+        impl crate::proto::Wired for #ident {
+            fn to_wire(&self, wire: &mut crate::proto::WireWrite) {
+                crate::proto::Wired::to_wire(&#repr::from(*self), wire)
+            }
+
+            fn from_wire(wire: &mut crate::proto::WireRead) -> Result<Self, crate::proto::Error> {
+                <#repr as crate::proto::Wired>::from_wire(wire).map(Self::from)
+            }
+        }
+    })
+}
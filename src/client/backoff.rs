@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff between reconnection attempts, randomized by ±20% like the Java client,
+/// so clients disconnected together don't reconnect in lockstep.
+pub(crate) struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, failures: 0 }
+    }
+
+    /// Delay after another failed attempt, doubling with every failure up to `max`.
+    pub(crate) fn next(&mut self) -> Duration {
+        let delay = self.initial.checked_mul(1 << self.failures.min(31)).map_or(self.max, |d| d.min(self.max));
+        self.failures = self.failures.saturating_add(1);
+        delay.mul_f64(rand::thread_rng().gen_range(0.8..1.2))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+#[test]
+fn test_backoff() {
+    let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+    let delays = (0..40).map(|_| backoff.next().as_millis()).collect::<Vec<_>>();
+    for (delay, expected) in delays.iter().zip(&[100, 200, 400, 800, 1000, 1000]) {
+        assert!(*delay >= expected * 8 / 10 && *delay <= expected * 12 / 10, "{:?}", delays);
+    }
+    assert!(delays[39] <= 1200);
+
+    backoff.reset();
+    assert!(backoff.next() <= Duration::from_millis(120));
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "tls")]
+use crate::config::TlsConfig;
+use crate::config::{Config, SaslConfig, SecurityConfig};
+use crate::transport::{Connector, Stream, TcpConnector};
+
+use super::Client;
+
+/// Builds a [Client], setting the most common options of the [Config] it is driven by.
+///
+/// ```no_run
+/// # async fn connect() -> rafka::Result<()> {
+/// let client = rafka::client::Client::builder()
+///     .client_id("billing")
+///     .request_timeout(std::time::Duration::from_secs(10))
+///     .connect("localhost:9092")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ClientBuilder {
+    config: Config,
+}
+
+impl From<Config> for ClientBuilder {
+    fn from(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.config.client_id = client_id.into();
+        self
+    }
+
+    /// Sets the name and version of the application reported to brokers, instead of those of rafka.
+    pub fn software(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.config.client_software_name = name.into();
+        self.config.client_software_version = version.into();
+        self
+    }
+
+    /// Sets the rack of the client, see [Config::client_rack].
+    pub fn rack(mut self, rack: impl Into<String>) -> Self {
+        self.config.client_rack = Some(rack.into());
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.config.max_in_flight = max_in_flight;
+        self
+    }
+
+    pub fn security(mut self, security: SecurityConfig) -> Self {
+        self.config.security = security;
+        self
+    }
+
+    pub fn sasl(mut self, sasl: SaslConfig) -> Self {
+        self.config.sasl = sasl;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.config.tls = tls;
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.config.socket.nodelay = nodelay;
+        self
+    }
+
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.config.socket.send_buffer_size = Some(size);
+        self
+    }
+
+    pub fn receive_buffer_size(mut self, size: usize) -> Self {
+        self.config.socket.receive_buffer_size = Some(size);
+        self
+    }
+
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.config.socket.keepalive = Some(idle);
+        self
+    }
+
+    /// Validated configuration, also usable for a [Cluster](super::Cluster).
+    pub fn build(self) -> crate::Result<Config> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+
+    /// Connects over TCP to `addr` in the `host:port` form.
+    pub async fn connect(self, addr: &str) -> crate::Result<Client> {
+        let connector = TcpConnector { config: self.config.clone() };
+        self.connect_via(&connector, addr).await
+    }
+
+    /// Connects to `addr` through `connector`.
+    pub async fn connect_via<C>(self, connector: &C, addr: &str) -> crate::Result<Client<C::Stream>>
+        where C: Connector + Clone
+    {
+        Client::connect_via(connector, addr, &self.build()?).await
+    }
+
+    /// Performs the handshake over an established connection, see [Client::from_stream].
+    pub async fn from_stream<S>(self, io: S) -> crate::Result<Client<S>>
+        where S: AsyncRead + AsyncWrite + Send + 'static
+    {
+        Client::from_stream(io, &self.build()?).await
+    }
+}
+
+#[tokio::test]
+async fn test_client_builder() {
+    use std::sync::{Arc, Mutex};
+    use crate::proto::{api_versions, ApiKey};
+    use crate::transport::mock;
+
+    // Records the client id and the software reported in ApiVersions
+    let seen = Arc::new(Mutex::new(vec![]));
+    let io = mock::spawn({
+        let seen = seen.clone();
+        move |req| {
+            let versions = req.decode::<api_versions::Request>();
+            seen.lock().unwrap().push((
+                req.header.client_id.clone(),
+                versions.client_software_name,
+                versions.client_software_version,
+            ));
+            Some(mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3)]))
+        }
+    });
+    let client = Client::builder()
+        .client_id("billing")
+        .software("billing-service", "2.1.0")
+        .max_in_flight(2)
+        .request_timeout(Duration::from_secs(3))
+        .from_stream(io)
+        .await
+        .unwrap();
+    assert_eq!(client.timeout, Duration::from_secs(3));
+    assert_eq!(client.in_flight.available_permits(), 2);
+    assert_eq!(seen.lock().unwrap()[0], (
+        Some("billing".to_string()),
+        Some("billing-service".to_string()),
+        Some("2.1.0".to_string()),
+    ));
+
+    let err = Client::builder().software("billing service", "2.1.0").build().unwrap_err();
+    assert_eq!(err.to_string(), "Invalid configuration: Invalid client software name \"billing service\"");
+    let config = ClientBuilder::from(Config { max_in_flight: 0, ..Default::default() });
+    assert!(config.from_stream(mock::spawn(|_| None)).await.is_err());
+}
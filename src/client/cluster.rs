@@ -0,0 +1,645 @@
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex, Weak};
+
+use tokio::sync::watch;
+
+use crate::client::cluster_metadata::ClusterMetadata;
+use crate::client::Client;
+use crate::config::Config;
+use crate::proto::{fetch, list_offsets, metadata, produce, ErrorCode};
+use crate::transport::{Connector, TcpConnector, ThrottleMetrics};
+use crate::{Error, KafkaCode, KafkaError};
+
+struct Inner<C>
+    where C: Connector
+{
+    /// Shared with the clients, which reconnect through it.
+    connector: Arc<C>,
+    config: Config,
+    bootstrap: Vec<String>,
+    /// Topics included in metadata refreshes, added when first routed to.
+    topics: Mutex<BTreeSet<String>>,
+    updates: watch::Sender<Arc<ClusterMetadata>>,
+    metadata: watch::Receiver<Arc<ClusterMetadata>>,
+    /// Keeps concurrent refreshes from publishing metadata out of order.
+    refresh: tokio::sync::Mutex<()>,
+    /// Connections by node id, bootstrap servers use negative ids since their node id is not known.
+    connections: Mutex<HashMap<i32, Slot<C::Stream>>>,
+}
+
+/// Connection to a node, empty until the first request for the node connected while the others waited.
+type Slot<S> = Arc<tokio::sync::Mutex<Option<Arc<Client<S>>>>>;
+
+/// Client of a whole cluster, discovering brokers from the metadata returned by the bootstrap servers.
+///
+/// Holds a single connection per broker, opened on first use, and routes partition requests to their leaders.
+/// The metadata is refreshed every [Config::metadata_max_age], and right after errors caused by stale metadata.
+pub struct Cluster<C = TcpConnector>
+    where C: Connector
+{
+    inner: Arc<Inner<C>>,
+}
+
+impl<C> Clone for Cluster<C>
+    where C: Connector
+{
+    fn clone(&self) -> Self {
+        Cluster { inner: self.inner.clone() }
+    }
+}
+
+impl Cluster {
+    /// Connects to the first reachable server of `bootstrap`, with the security settings of `config`.
+    pub async fn connect(bootstrap: &[&str], config: &Config) -> crate::Result<Cluster> {
+        let connector = TcpConnector { config: config.clone() };
+        Self::with_connector(connector, bootstrap, config).await
+    }
+
+    /// Connects to the first reachable server of [Config::bootstrap_servers].
+    pub async fn from_config(config: &Config) -> crate::Result<Cluster> {
+        let bootstrap = config.bootstrap_servers.iter().map(String::as_str).collect::<Vec<_>>();
+        Self::connect(&bootstrap, config).await
+    }
+}
+
+impl<C> Cluster<C>
+    where C: Connector
+{
+    /// Connects through `connector` to the first reachable server of `bootstrap` and loads the brokers of the cluster.
+    pub async fn with_connector(connector: C, bootstrap: &[&str], config: &Config) -> crate::Result<Cluster<C>> {
+        if bootstrap.is_empty() {
+            return Err(Error::Config("At least one bootstrap server is required".to_string()));
+        }
+        let (updates, metadata) = watch::channel(Arc::new(ClusterMetadata::default()));
+        let cluster = Cluster {
+            inner: Arc::new(Inner {
+                connector: Arc::new(connector),
+                config: config.clone(),
+                bootstrap: bootstrap.iter().map(|s| s.to_string()).collect(),
+                topics: Mutex::new(BTreeSet::new()),
+                updates,
+                metadata,
+                refresh: tokio::sync::Mutex::new(()),
+                connections: Mutex::new(HashMap::new()),
+            }),
+        };
+        cluster.refresh_metadata().await?;
+        Self::spawn_refresh(Arc::downgrade(&cluster.inner), config.metadata_max_age);
+        Ok(cluster)
+    }
+
+    /// Refreshes the metadata periodically until the cluster is dropped, a zero `interval` disables it.
+    fn spawn_refresh(inner: Weak<Inner<C>>, interval: std::time::Duration) {
+        if interval.as_nanos() == 0 {
+            return;
+        }
+        tokio::spawn(async move {
+            loop {
+                tokio::time::delay_for(interval).await;
+                let cluster = match inner.upgrade() {
+                    Some(inner) => Cluster { inner },
+                    None => return,
+                };
+                // Failures are retried on the next tick, requests also refresh after their own errors
+                let _ = cluster.refresh_metadata().await;
+            }
+        });
+    }
+
+    /// Connection to `node_id`, opened to `addr` unless already open.
+    ///
+    /// Only requests for the same node wait for the connection to be established.
+    async fn connection(&self, node_id: i32, addr: &str) -> crate::Result<Arc<Client<C::Stream>>> {
+        let slot = self.inner.connections.lock().unwrap().entry(node_id).or_default().clone();
+        let mut slot = slot.lock().await;
+        if let Some(client) = &*slot {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(Client::connect_via(&self.inner.connector, addr, &self.inner.config).await?);
+        *slot = Some(client.clone());
+        Ok(client)
+    }
+
+    /// Open connections, skipping those still being established.
+    fn open_connections(&self) -> Vec<(i32, Arc<Client<C::Stream>>)> {
+        let connections = self.inner.connections.lock().unwrap();
+        connections.iter()
+            .filter_map(|(id, slot)| Some((*id, slot.try_lock().ok()?.clone()?)))
+            .collect()
+    }
+
+    /// Fetches the metadata of `topics` from the first open connection to answer,
+    /// then from the first reachable bootstrap server.
+    async fn fetch_metadata(&self, topics: Vec<String>) -> crate::Result<metadata::Response> {
+        let mut last_err = None;
+        for (_, client) in self.open_connections() {
+            match client.metadata(topics.clone()).await {
+                Ok(res) => return Ok(res),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        for (i, addr) in self.inner.bootstrap.iter().enumerate() {
+            let res = match self.connection(-1 - i as i32, addr).await {
+                Ok(client) => client.metadata(topics.clone()).await,
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(res) => return Ok(res),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    /// Last fetched metadata of the cluster.
+    pub fn metadata(&self) -> Arc<ClusterMetadata> {
+        self.inner.metadata.borrow().clone()
+    }
+
+    /// Receiver of the metadata, notified whenever a refresh changes it.
+    ///
+    /// The first `recv` returns the current metadata immediately.
+    pub fn watch(&self) -> watch::Receiver<Arc<ClusterMetadata>> {
+        self.inner.metadata.clone()
+    }
+
+    /// Throttling of each open connection by node id, see [Client::throttle_metrics].
+    ///
+    /// Bootstrap connections, opened before the node ids are known, have negative ids.
+    pub fn throttle_metrics(&self) -> HashMap<i32, ThrottleMetrics> {
+        self.open_connections().into_iter().map(|(id, client)| (id, client.throttle_metrics())).collect()
+    }
+
+    /// Fetches the brokers and the metadata of the topics routed to so far,
+    /// and closes the connections to brokers which left the cluster.
+    pub async fn refresh_metadata(&self) -> crate::Result<Arc<ClusterMetadata>> {
+        let _guard = self.inner.refresh.lock().await;
+        let topics = self.inner.topics.lock().unwrap().iter().cloned().collect();
+        let res = self.fetch_metadata(topics).await?;
+
+        let metadata = Arc::new(ClusterMetadata::from(&res));
+        self.inner.connections.lock().unwrap().retain(|id, _| *id < 0 || metadata.brokers.contains_key(id));
+        if *self.inner.metadata.borrow() != metadata {
+            // Can't fail, since the cluster holds a receiver
+            let _ = self.inner.updates.broadcast(metadata.clone());
+        }
+        Ok(metadata)
+    }
+
+    /// Refreshes the metadata when `error` is caused by stale metadata, like a leader change.
+    pub async fn handle_error(&self, error: &KafkaError) -> crate::Result<()> {
+        if error.requires_metadata_refresh() {
+            self.refresh_metadata().await?;
+        }
+        Ok(())
+    }
+
+    /// Connection to the broker with `node_id`, opened when first requested.
+    pub async fn broker(&self, node_id: i32) -> crate::Result<Arc<Client<C::Stream>>> {
+        let addr = self.metadata().brokers.get(&node_id).map(|b| b.addr())
+            .ok_or_else(|| KafkaError {
+                code: KafkaCode::BrokerNotAvailable,
+                message: Some(format!("Broker {} is not part of the cluster", node_id)),
+            })?;
+        self.connection(node_id, &addr).await
+    }
+
+    /// Node id of the leader of `partition`, fetching the metadata of `topic` when it is not known.
+    pub async fn leader(&self, topic: &str, partition: i32) -> crate::Result<i32> {
+        if let Some(leader) = self.metadata().partition(topic, partition).and_then(|p| p.leader) {
+            return Ok(leader);
+        }
+
+        self.inner.topics.lock().unwrap().insert(topic.to_string());
+        let metadata = self.refresh_metadata().await?;
+        let topic = metadata.topic(topic).ok_or(KafkaError::from(KafkaCode::UnknownTopicOrPartition))?;
+        if let Some(code) = topic.error {
+            return Err(KafkaError::from(code).into());
+        }
+        let partition = topic.partitions.get(&partition).ok_or(KafkaError::from(KafkaCode::UnknownTopicOrPartition))?;
+        Ok(partition.leader.ok_or(KafkaError::from(KafkaCode::LeaderNotAvailable))?)
+    }
+
+    /// Groups the partitions of `topics` by the node id of their leader, `index` is the partition of each item.
+    async fn by_leader<P>(&self, topics: Vec<(String, Vec<P>)>, index: impl Fn(&P) -> i32)
+                          -> crate::Result<HashMap<i32, Vec<(String, Vec<P>)>>>
+    {
+        let mut by_leader = HashMap::<i32, Vec<(String, Vec<P>)>>::new();
+        for (topic, parts) in topics {
+            for part in parts {
+                let leader = self.leader(&topic, index(&part)).await?;
+                let requests = by_leader.entry(leader).or_default();
+                match requests.iter_mut().find(|(t, _)| *t == topic) {
+                    Some((_, parts)) => parts.push(part),
+                    None => requests.push((topic.clone(), vec![part])),
+                }
+            }
+        }
+        Ok(by_leader)
+    }
+
+    /// Sends the request built by `send` to each leader, refreshing the metadata when a leader can't be reached.
+    async fn send_to_leaders<P, F, R>(
+        &self,
+        by_leader: HashMap<i32, Vec<(String, Vec<P>)>>,
+        send: impl Fn(Arc<Client<C::Stream>>, Vec<(String, Vec<P>)>) -> F,
+    ) -> crate::Result<Vec<R>>
+        where F: Future<Output=crate::Result<R>>
+    {
+        let send = &send;
+        let requests = by_leader.into_iter().map(|(leader, topics)| async move {
+            send(self.broker(leader).await?, topics).await
+        });
+        match futures::future::try_join_all(requests).await {
+            Ok(responses) => Ok(responses),
+            Err(err) => {
+                // The partitions may have moved away from an unreachable leader, refresh failures are left to the next request
+                if err.is_retriable() {
+                    let _ = self.refresh_metadata().await;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Refreshes the metadata once when any of the partition `errors` is caused by stale metadata.
+    async fn handle_errors(&self, mut errors: impl Iterator<Item=KafkaError>) -> crate::Result<()> {
+        match errors.find(KafkaError::requires_metadata_refresh) {
+            Some(error) => self.handle_error(&error).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Lists offsets of partitions, sending one request to the leader of each group of partitions.
+    pub async fn list_offsets(&self, topics: Vec<(String, Vec<usize>)>) -> crate::Result<list_offsets::Response> {
+        let by_leader = self.by_leader(topics, |part| *part as i32).await?;
+        let responses = self.send_to_leaders(by_leader, |client, topics| client.list_offsets(topics)).await?;
+
+        let mut res = list_offsets::Response {
+            throttle_time_ms: None,
+            topics: vec![],
+        };
+        for part in responses {
+            res.throttle_time_ms = res.throttle_time_ms.max(part.throttle_time_ms);
+            res.topics.extend(part.topics);
+        }
+        self.handle_errors(res.errors().map(|(_, _, error)| error)).await?;
+        Ok(res)
+    }
+
+    /// Produces records, sending the partitions of `req` to their leaders with the same acks and timeout.
+    ///
+    /// Fails with [Error::Config] when `acks` is 0, since brokers don't answer such requests.
+    pub async fn produce(&self, req: produce::Request) -> crate::Result<produce::Response> {
+        if req.acks == 0 {
+            return Err(Error::Config("Producing with acks 0 is not supported".to_string()));
+        }
+        let topics = req.topic_data.into_iter().map(|t| (t.name, t.partition_data)).collect();
+        let by_leader = self.by_leader(topics, |part| part.index).await?;
+        let (transactional_id, acks, timeout_ms) = (req.transactional_id, req.acks, req.timeout_ms);
+        let responses = self.send_to_leaders(by_leader, |client, topics| {
+            client.call(produce::Request {
+                transactional_id: transactional_id.clone(),
+                acks,
+                timeout_ms,
+                topic_data: topics.into_iter()
+                    .map(|(name, partition_data)| produce::TopicProduceData { name, partition_data })
+                    .collect(),
+            })
+        }).await?;
+
+        let mut res = produce::Response {
+            responses: vec![],
+            throttle_time_ms: None,
+        };
+        for part in responses {
+            res.throttle_time_ms = res.throttle_time_ms.max(part.throttle_time_ms);
+            res.responses.extend(part.responses);
+        }
+        self.handle_errors(res.responses.iter().flat_map(|t| &t.partition_responses).filter_map(|p| p.error().err())).await?;
+        Ok(res)
+    }
+
+    /// Fetches records, sending the partitions of `req` to their leaders with the same limits.
+    ///
+    /// Sessions are tied to a broker, so each leader gets a full fetch request outside of any session.
+    pub async fn fetch(&self, req: fetch::Request) -> crate::Result<fetch::Response> {
+        let topics = req.topics.iter().map(|t| (t.topic.clone(), t.partitions.clone())).collect();
+        let by_leader = self.by_leader(topics, |part| part.partition).await?;
+        let req = fetch::Request {
+            session_id: Some(0),
+            session_epoch: Some(-1),
+            topics: vec![],
+            forgotten_topics_data: Some(vec![]),
+            ..req
+        };
+        let responses = self.send_to_leaders(by_leader, |client, topics| {
+            client.fetch(fetch::Request {
+                topics: topics.into_iter().map(|(topic, partitions)| fetch::FetchTopic { topic, partitions }).collect(),
+                ..req.clone()
+            })
+        }).await?;
+
+        let mut res = fetch::Response {
+            throttle_time_ms: None,
+            error_code: None,
+            session_id: None,
+            responses: vec![],
+        };
+        for part in responses {
+            res.throttle_time_ms = res.throttle_time_ms.max(part.throttle_time_ms);
+            res.error_code = res.error_code.filter(|code| *code != 0).or(part.error_code);
+            res.session_id = part.session_id;
+            res.responses.extend(part.responses);
+        }
+        self.handle_errors(res.partitions().filter_map(|(_, p)| p.error().err())).await?;
+        Ok(res)
+    }
+}
+
+/// Broker of a cluster with nodes 1 and 2, the leader of each partition of `test` is looked up in `leaders`.
+#[cfg(test)]
+fn test_broker(node_id: i32, leaders: Arc<Mutex<Vec<(i32, i32)>>>) -> tokio::net::UnixStream {
+    use crate::proto::{metadata, ApiKey};
+    use crate::proto::list_offsets::{ListOffsetsPartitionResponse, ListOffsetsTopicResponse};
+    use crate::transport::mock;
+
+    mock::spawn(move |req| match req.header.api_key {
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[
+            (ApiKey::ApiVersions, 0, 3),
+            (ApiKey::Metadata, 0, 9),
+            (ApiKey::ListOffsets, 0, 5),
+            (ApiKey::Produce, 0, 8),
+            (ApiKey::Fetch, 0, 11),
+        ])),
+        ApiKey::Metadata => {
+            let topics = req.decode::<metadata::Request>().topics.unwrap_or_default();
+            let leaders = leaders.lock().unwrap().clone();
+            let topics = if topics.is_empty() { vec![] } else { vec![("test", &leaders[..])] };
+            Some(req.respond::<metadata::Request>(&metadata::Response::new(&[(1, "b1"), (2, "b2")], &topics)))
+        }
+        ApiKey::ListOffsets => {
+            let topics = req.decode::<list_offsets::Request>().topics;
+            let leaders = leaders.lock().unwrap().clone();
+            let topics = topics.into_iter().map(|t| ListOffsetsTopicResponse {
+                name: t.name,
+                partitions: t.partitions.into_iter().map(|p| ListOffsetsPartitionResponse {
+                    partition_index: p.partition_index,
+                    error_code: if leaders.contains(&(p.partition_index, node_id)) { 0 } else { KafkaCode::NotLeaderOrFollower.into() },
+                    offset: Some(node_id as i64 * 100 + p.partition_index as i64),
+                    ..Default::default()
+                }).collect(),
+            }).collect();
+            Some(req.respond::<list_offsets::Request>(&list_offsets::Response {
+                throttle_time_ms: Some(node_id),
+                topics,
+            }))
+        }
+        ApiKey::Produce => {
+            let topics = req.decode::<produce::Request>().topic_data;
+            let leaders = leaders.lock().unwrap().clone();
+            let responses = topics.into_iter().map(|t| produce::TopicProduceResponse {
+                name: t.name,
+                partition_responses: t.partition_data.into_iter().map(|p| produce::PartitionProduceResponse {
+                    index: p.index,
+                    error_code: if leaders.contains(&(p.index, node_id)) { 0 } else { KafkaCode::NotLeaderOrFollower.into() },
+                    base_offset: node_id as i64 * 100 + p.index as i64,
+                    ..Default::default()
+                }).collect(),
+            }).collect();
+            Some(req.respond::<produce::Request>(&produce::Response { responses, throttle_time_ms: Some(node_id) }))
+        }
+        ApiKey::Fetch => {
+            let topics = req.decode::<fetch::Request>().topics;
+            let leaders = leaders.lock().unwrap().clone();
+            let responses = topics.into_iter().map(|t| fetch::FetchableTopicResponse {
+                topic: t.topic,
+                partitions: t.partitions.into_iter().map(|p| fetch::PartitionData {
+                    partition_index: p.partition,
+                    error_code: if leaders.contains(&(p.partition, node_id)) { 0 } else { KafkaCode::NotLeaderOrFollower.into() },
+                    high_watermark: node_id as i64 * 100 + p.partition as i64,
+                    ..Default::default()
+                }).collect(),
+            }).collect();
+            Some(req.respond::<fetch::Request>(&fetch::Response { responses, ..Default::default() }))
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+fn test_connector(
+    leaders: Arc<Mutex<Vec<(i32, i32)>>>,
+    connects: Arc<std::sync::atomic::AtomicUsize>,
+) -> impl Connector<Stream = tokio::net::UnixStream> {
+    crate::transport::mock::MockConnector(move |addr: &str| {
+        connects.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        match addr {
+            "b1:9092" => Some(test_broker(1, leaders.clone())),
+            "b2:9092" => Some(test_broker(2, leaders.clone())),
+            _ => None,
+        }
+    })
+}
+
+#[tokio::test]
+async fn test_cluster_routing() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let connects = Arc::new(AtomicUsize::new(0));
+    let leaders = Arc::new(Mutex::new(vec![(0, 2), (1, 1), (2, 2)]));
+    let connector = test_connector(leaders, connects.clone());
+
+    // The unreachable server is skipped
+    let cluster = Cluster::with_connector(connector, &["down:9092", "b2:9092"], &Config::default()).await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+    assert_eq!(cluster.leader("test", 1).await.unwrap(), 1);
+
+    let res = cluster.list_offsets(vec![("test".to_string(), vec![0, 1, 2])]).await.unwrap();
+    assert_eq!(res.errors().count(), 0);
+    let mut offsets = res.topics.iter()
+        .flat_map(|t| t.partitions.iter().map(|p| (p.partition_index, p.offset)))
+        .collect::<Vec<_>>();
+    offsets.sort();
+    assert_eq!(offsets, vec![(0, Some(200)), (1, Some(101)), (2, Some(202))]);
+    assert_eq!(res.throttle_time_ms, Some(2));
+
+    // Connections are reused, only broker 1 was connected to in addition to the bootstrap servers
+    cluster.list_offsets(vec![("test".to_string(), vec![1])]).await.unwrap();
+    assert_eq!(connects.load(Ordering::SeqCst), 4);
+
+    assert!(cluster.broker(3).await.is_err());
+    assert!(cluster.leader("test", 7).await.is_err());
+}
+
+#[tokio::test]
+async fn test_cluster_metadata_refresh() {
+    use std::time::Duration;
+
+    let leaders = Arc::new(Mutex::new(vec![(0, 1), (1, 1)]));
+    let connector = test_connector(leaders.clone(), Default::default());
+    let config = Config {
+        metadata_max_age: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let cluster = Cluster::with_connector(connector, &["b1:9092"], &config).await.unwrap();
+    assert_eq!(cluster.metadata().brokers[&2].addr(), "b2:9092");
+    assert!(cluster.metadata().topics.is_empty());
+
+    let mut watch = cluster.watch();
+    assert!(watch.recv().await.unwrap().topics.is_empty());
+
+    cluster.list_offsets(vec![("test".to_string(), vec![0, 1])]).await.unwrap();
+    let metadata = watch.recv().await.unwrap();
+    assert_eq!(metadata.leader("test", 1).unwrap().node_id, 1);
+    assert_eq!(metadata.partition("test", 1).unwrap().isr, vec![1]);
+
+    // The stale leader answers with NotLeaderOrFollower, which triggers a refresh
+    *leaders.lock().unwrap() = vec![(0, 1), (1, 2)];
+    let res = cluster.list_offsets(vec![("test".to_string(), vec![1])]).await.unwrap();
+    assert_eq!(res.errors().next().unwrap().2.code, KafkaCode::NotLeaderOrFollower);
+    assert_eq!(cluster.metadata().leader("test", 1).unwrap().node_id, 2);
+    assert_eq!(watch.recv().await.unwrap().leader("test", 1).unwrap().node_id, 2);
+
+    // Picked up by the periodic refresh, without any requests
+    *leaders.lock().unwrap() = vec![(0, 2), (1, 2)];
+    let metadata = tokio::time::timeout(Duration::from_secs(5), watch.recv()).await.unwrap().unwrap();
+    assert_eq!(metadata.leader("test", 0).unwrap().node_id, 2);
+}
+
+#[tokio::test]
+async fn test_cluster_produce_fetch() {
+    let leaders = Arc::new(Mutex::new(vec![(0, 2), (1, 1)]));
+    let connector = test_connector(leaders.clone(), Default::default());
+    let config = Config { metadata_max_age: Default::default(), ..Default::default() };
+    let cluster = Cluster::with_connector(connector, &["b1:9092"], &config).await.unwrap();
+
+    let produce = |parts: &[i32]| produce::Request {
+        acks: -1,
+        timeout_ms: 1000,
+        topic_data: vec![produce::TopicProduceData {
+            name: "test".to_string(),
+            partition_data: parts.iter().map(|&index| produce::PartitionProduceData { index, ..Default::default() }).collect(),
+        }],
+        ..Default::default()
+    };
+    let res = cluster.produce(produce(&[0, 1])).await.unwrap();
+    let mut offsets = res.responses.iter()
+        .flat_map(|t| t.partition_responses.iter().map(|p| (p.index, p.error_code, p.base_offset)))
+        .collect::<Vec<_>>();
+    offsets.sort();
+    assert_eq!(offsets, vec![(0, 0, 200), (1, 0, 101)]);
+    assert_eq!(res.throttle_time_ms, Some(2));
+
+    let fetch = |parts: &[i32]| fetch::Request {
+        topics: vec![fetch::FetchTopic {
+            topic: "test".to_string(),
+            partitions: parts.iter().map(|&partition| fetch::FetchPartition { partition, ..Default::default() }).collect(),
+        }],
+        ..Default::default()
+    };
+    let res = cluster.fetch(fetch(&[0, 1])).await.unwrap();
+    let mut offsets = res.partitions().map(|(_, p)| (p.partition_index, p.error_code, p.high_watermark)).collect::<Vec<_>>();
+    offsets.sort();
+    assert_eq!(offsets, vec![(0, 0, 200), (1, 0, 101)]);
+
+    // Stale leaders answer with NotLeaderOrFollower, which triggers a refresh
+    *leaders.lock().unwrap() = vec![(0, 1), (1, 1)];
+    let res = cluster.produce(produce(&[0])).await.unwrap();
+    assert_eq!(res.responses[0].partition_responses[0].error().unwrap_err().code, KafkaCode::NotLeaderOrFollower);
+    assert_eq!(cluster.metadata().leader("test", 0).unwrap().node_id, 1);
+
+    *leaders.lock().unwrap() = vec![(0, 2), (1, 1)];
+    let res = cluster.fetch(fetch(&[0])).await.unwrap();
+    assert_eq!(res.partitions().next().unwrap().1.error().unwrap_err().code, KafkaCode::NotLeaderOrFollower);
+    assert_eq!(cluster.metadata().leader("test", 0).unwrap().node_id, 2);
+
+    assert!(matches!(cluster.produce(produce::Request { acks: 0, ..produce(&[0]) }).await, Err(Error::Config(_))));
+}
+
+#[tokio::test]
+async fn test_cluster_unreachable_leader() {
+    let leaders = Arc::new(Mutex::new(vec![(0, 2)]));
+    let connector = {
+        let leaders = leaders.clone();
+        crate::transport::mock::MockConnector(move |addr: &str| match addr {
+            "b1:9092" => Some(test_broker(1, leaders.clone())),
+            _ => {
+                // Broker 2 went down, and its partitions moved to broker 1
+                *leaders.lock().unwrap() = vec![(0, 1)];
+                None
+            }
+        })
+    };
+    let config = Config { metadata_max_age: Default::default(), ..Default::default() };
+    let cluster = Cluster::with_connector(connector, &["b1:9092"], &config).await.unwrap();
+
+    let err = cluster.list_offsets(vec![("test".to_string(), vec![0])]).await.unwrap_err();
+    assert!(matches!(err, Error::Io(_)), "{}", err);
+    assert_eq!(cluster.metadata().leader("test", 0).unwrap().node_id, 1);
+    cluster.list_offsets(vec![("test".to_string(), vec![0])]).await.unwrap();
+
+    // Same for the other requests routed to leaders
+    *leaders.lock().unwrap() = vec![(0, 2)];
+    cluster.refresh_metadata().await.unwrap();
+    let req = fetch::Request {
+        topics: vec![fetch::FetchTopic {
+            topic: "test".to_string(),
+            partitions: vec![fetch::FetchPartition { partition: 0, ..Default::default() }],
+        }],
+        ..Default::default()
+    };
+    assert!(cluster.fetch(req.clone()).await.is_err());
+    assert_eq!(cluster.metadata().leader("test", 0).unwrap().node_id, 1);
+    cluster.fetch(req).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cluster_failover() {
+    use std::collections::HashSet;
+    use crate::proto::ApiKey;
+    use crate::transport::mock;
+
+    // Brokers of the cluster, and the addresses which stopped answering
+    let brokers = Arc::new(Mutex::new(vec![(1, "b1"), (2, "b2")]));
+    let down = Arc::new(Mutex::new(HashSet::new()));
+    let connector = {
+        let (brokers, down) = (brokers.clone(), down.clone());
+        mock::MockConnector(move |addr: &str| {
+            if down.lock().unwrap().contains(addr) {
+                return None;
+            }
+            let (addr, brokers, down) = (addr.to_string(), brokers.clone(), down.clone());
+            Some(mock::spawn(move |req| match req.header.api_key {
+                _ if down.lock().unwrap().contains(&addr) => None,
+                ApiKey::ApiVersions => Some(mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3), (ApiKey::Metadata, 0, 9)])),
+                ApiKey::Metadata => {
+                    let brokers = brokers.lock().unwrap().clone();
+                    Some(req.respond::<metadata::Request>(&metadata::Response::new(&brokers, &[])))
+                }
+                _ => None,
+            }))
+        })
+    };
+    let cluster = Cluster::with_connector(connector, &["b1:9092", "b3:9092"], &Config::default()).await.unwrap();
+    cluster.broker(1).await.unwrap();
+    cluster.broker(2).await.unwrap();
+
+    // Connections to brokers which left the cluster are closed
+    *brokers.lock().unwrap() = vec![(2, "b2"), (3, "b3")];
+    cluster.refresh_metadata().await.unwrap();
+    let mut nodes = cluster.throttle_metrics().keys().cloned().collect::<Vec<_>>();
+    nodes.sort();
+    assert_eq!(nodes, vec![-1, 2]);
+
+    // Refreshed through another connection once the bootstrap server is down
+    down.lock().unwrap().insert("b1:9092".to_string());
+    cluster.refresh_metadata().await.unwrap();
+
+    // Falls back to the next bootstrap server once all open connections are down
+    down.lock().unwrap().insert("b2:9092".to_string());
+    *brokers.lock().unwrap() = vec![(3, "b3")];
+    assert_eq!(cluster.refresh_metadata().await.unwrap().brokers.len(), 1);
+    assert!(cluster.throttle_metrics().contains_key(&-2));
+}
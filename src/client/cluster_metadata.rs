@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::proto::metadata;
+use crate::KafkaCode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokerMetadata {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: Option<String>,
+}
+
+impl BrokerMetadata {
+    /// Address in the `host:port` form.
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionMetadata {
+    pub id: i32,
+    /// Node id of the leader, `None` during leader elections.
+    pub leader: Option<i32>,
+    pub leader_epoch: Option<i32>,
+    pub replicas: Vec<i32>,
+    pub isr: Vec<i32>,
+    pub offline_replicas: Vec<i32>,
+    pub error: Option<KafkaCode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicMetadata {
+    pub name: String,
+    pub is_internal: bool,
+    pub partitions: BTreeMap<i32, PartitionMetadata>,
+    pub error: Option<KafkaCode>,
+}
+
+/// Brokers and topics of a cluster, as returned by the last Metadata request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClusterMetadata {
+    pub cluster_id: Option<String>,
+    pub controller_id: Option<i32>,
+    pub brokers: HashMap<i32, BrokerMetadata>,
+    pub topics: HashMap<String, TopicMetadata>,
+}
+
+fn error(code: i16) -> Option<KafkaCode> {
+    (code != 0).then_some(KafkaCode::from(code))
+}
+
+impl ClusterMetadata {
+    pub fn topic(&self, name: &str) -> Option<&TopicMetadata> {
+        self.topics.get(name)
+    }
+
+    pub fn partition(&self, topic: &str, partition: i32) -> Option<&PartitionMetadata> {
+        self.topics.get(topic)?.partitions.get(&partition)
+    }
+
+    /// Broker leading `partition`, if it is known and has a leader.
+    pub fn leader(&self, topic: &str, partition: i32) -> Option<&BrokerMetadata> {
+        self.brokers.get(&self.partition(topic, partition)?.leader?)
+    }
+}
+
+impl From<&metadata::Response> for ClusterMetadata {
+    fn from(res: &metadata::Response) -> Self {
+        let brokers = res.brokers.iter().map(|b| {
+            let broker = BrokerMetadata {
+                node_id: b.node_id,
+                host: b.host.clone(),
+                port: b.port,
+                rack: b.rack.clone().flatten(),
+            };
+            (b.node_id, broker)
+        }).collect();
+
+        let topics = res.topics.iter().map(|t| {
+            let partitions = t.partitions.iter().map(|p| {
+                let partition = PartitionMetadata {
+                    id: p.partition_index,
+                    leader: Some(p.leader_id).filter(|id| *id >= 0),
+                    leader_epoch: p.leader_epoch.filter(|epoch| *epoch >= 0),
+                    replicas: p.replica_nodes.clone(),
+                    isr: p.isr_nodes.clone(),
+                    offline_replicas: p.offline_replicas.clone().unwrap_or_default(),
+                    error: error(p.error_code),
+                };
+                (p.partition_index, partition)
+            }).collect();
+
+            let topic = TopicMetadata {
+                name: t.name.clone(),
+                is_internal: t.is_internal.unwrap_or(false),
+                partitions,
+                error: error(t.error_code),
+            };
+            (t.name.clone(), topic)
+        }).collect();
+
+        ClusterMetadata {
+            cluster_id: res.cluster_id.clone().flatten(),
+            controller_id: res.controller_id.filter(|id| *id >= 0),
+            brokers,
+            topics,
+        }
+    }
+}
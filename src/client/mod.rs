@@ -1,55 +1,641 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
 use tower::{Service, ServiceExt};
 use tokio::io::{AsyncWrite, AsyncRead};
 use crate::transport;
+use crate::transport::{Connector, Stream, TcpConnector, Throttle, ThrottleMetrics};
+use crate::config::Config;
 use transport::CallReq;
 use tokio::net::{ToSocketAddrs, TcpStream};
 use std::future::Future;
-use crate::proto::{ TopicMap, TopicItem, ApiKey, IsolationLevel};
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use futures::future::poll_fn;
+use crate::proto::{ TopicMap, TopicItem, ApiKey, ApiRequest, IsolationLevel, negotiate_version};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{oneshot, Mutex, Semaphore};
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::time;
+use futures::future::{self, poll_fn, BoxFuture, Either};
 use std::ops::DerefMut;
+use crate::proto::api_versions;
+use crate::{Error, KafkaCode, KafkaError};
+use crate::sasl;
 
-pub struct Client
+mod backoff;
+mod builder;
+mod cluster;
+mod cluster_metadata;
+
+pub use builder::ClientBuilder;
+pub use cluster::Cluster;
+pub use cluster_metadata::{BrokerMetadata, ClusterMetadata, PartitionMetadata, TopicMetadata};
+
+use backoff::Backoff;
+
+/// Highest supported version of the ApiVersions request.
+const API_VERSIONS_MAX: usize = 3;
+
+/// Opens a new stream to the broker the client was connected to.
+type Connect<S> = Arc<dyn Fn() -> BoxFuture<'static, io::Result<S>> + Send + Sync>;
+
+/// Versions of each api supported by the broker.
+type ApiVersions = HashMap<ApiKey, (usize, usize)>;
+
+/// Connection to a broker, over TCP by default or any stream created by a [Connector].
+///
+/// Clones share the connection, requests sent through them are multiplexed over it.
+/// Connections opened with [Client::connect_via] are re-established when they break,
+/// requests in flight at that time fail with retriable errors.
+pub struct Client<S = Stream>
+    where S: AsyncRead + AsyncWrite + Send + 'static
 {
-    // TODO: make this generic
-    pub(crate) client: Arc<Mutex<transport::TypedClient<TcpStream>>>,
-    pub(crate) api_versions: HashMap<crate::proto::ApiKey, (usize, usize)>,
+    pub(crate) conn: Arc<Mutex<Connection<S>>>,
+    /// Versions of the current connection, updated on reconnection.
+    pub(crate) api_versions: Arc<RwLock<ApiVersions>>,
+    /// Versions negotiated by the requests sent so far.
+    pub(crate) selected: Arc<std::sync::Mutex<BTreeMap<ApiKey, usize>>>,
+    /// Slots of the requests in flight, limited by [Config::max_in_flight].
+    pub(crate) in_flight: Arc<Semaphore>,
+    pub(crate) timeout: Duration,
+    /// [Config::client_rack], set in fetch requests.
+    pub(crate) rack: Option<String>,
+    throttle: Arc<Throttle>,
+}
+
+/// Transport and authenticated session of a connection, replaced on reconnection.
+pub(crate) struct Connection<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    /// `None` once the connection broke, until it is re-established.
+    pub(crate) client: Option<transport::TypedClient<S>>,
+    pub(crate) sasl: Option<sasl::Session>,
+    config: Config,
+    /// `None` for connections over a single stream, which can't be re-established.
+    connect: Option<Connect<S>>,
+    backoff: Backoff,
+    /// Earliest instant of the next reconnection attempt, set after failed attempts.
+    retry_at: Option<Instant>,
+    /// Kept across reconnections, the broker quota still applies to the new connection.
+    throttle: Arc<Throttle>,
+}
+
+impl<S> Clone for Client<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    fn clone(&self) -> Self {
+        Client {
+            conn: self.conn.clone(),
+            api_versions: self.api_versions.clone(),
+            selected: self.selected.clone(),
+            in_flight: self.in_flight.clone(),
+            timeout: self.timeout,
+            rack: self.rack.clone(),
+            throttle: self.throttle.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Client<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("selected_versions", &self.selected_versions())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
 }
 
 
 impl Client {
-    pub async fn connect(addr: impl ToSocketAddrs) -> anyhow::Result<Client> {
-        let io = TcpStream::connect(addr).await?;
-        let mut client = transport::new(io).await;
+    /// Builder of a client, starting from the default [Config].
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
 
-        let request = crate::proto::api_versions::Request {
-            client_software_name: Some("rafka".to_string()),
-            client_software_version: Some("0.0.0".to_string()),
-            tags: None,
-        };
-        let req = CallReq::new(2, request);
+    /// Connects to `addr` without TLS or SASL, reconnecting to the same resolved addresses.
+    pub async fn connect(addr: impl ToSocketAddrs) -> crate::Result<Client> {
+        let addrs = tokio::net::lookup_host(addr).await?.collect::<Vec<_>>();
+        let connect: Connect<Stream> = Arc::new(move || {
+            let addrs = addrs.clone();
+            Box::pin(async move { Ok(Stream::Plain(TcpStream::connect(&addrs[..]).await?)) })
+        });
+        Self::establish(connect().await?, &Config::default(), Some(connect)).await
+    }
 
-        let ready = ServiceExt::<CallReq<crate::proto::api_versions::Request>>::ready_and(&mut client);
-        let versions = ready.await?.call(req).await?;
-        crate::res_from_code(versions.error_code).unwrap();
-        let api_versions = versions.versions.into_iter().map(|v| {
-            (v.api_key, (v.min_version as usize, v.max_version as usize))
-        }).collect();
+    /// Connects to `addr` in the `host:port` form, with the security settings of `config`.
+    pub async fn connect_with(addr: &str, config: &Config) -> crate::Result<Client> {
+        let connector = TcpConnector { config: config.clone() };
+        Self::connect_via(&connector, addr, config).await
+    }
+}
+
+impl<S> Client<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    /// Connects to `addr` through `connector`, authenticating with the SASL settings of `config`.
+    ///
+    /// The connector is kept to reconnect, with the backoff of [Config::reconnect_backoff].
+    pub async fn connect_via<C>(connector: &C, addr: &str, config: &Config) -> crate::Result<Client<S>>
+        where C: Connector<Stream = S> + Clone
+    {
+        let connector = connector.clone();
+        let addr = addr.to_string();
+        let connect: Connect<S> = Arc::new(move || {
+            let (connector, addr) = (connector.clone(), addr.clone());
+            Box::pin(async move {
+                connector.connect(&addr).await
+                    .map_err(|e| io::Error::new(e.kind(), format!("Connecting to {}: {}", addr, e)))
+            })
+        });
+        Self::establish(connect().await?, config, Some(connect)).await
+    }
+
+    /// Performs the handshake over an established connection, which must already be encrypted if required,
+    /// the TLS and socket settings of `config` are not used.
+    ///
+    /// The connection is not re-established once the stream closes.
+    pub async fn from_stream(io: S, config: &Config) -> crate::Result<Client<S>> {
+        Self::establish(io, config, None).await
+    }
+
+    async fn establish(io: S, config: &Config, connect: Option<Connect<S>>) -> crate::Result<Client<S>> {
+        config.validate()?;
+        let (client, api_versions, sasl) = Self::handshake(io, config).await?;
+        let throttle = client.throttle().clone();
+        let conn = Connection {
+            client: Some(client),
+            sasl,
+            config: config.clone(),
+            connect,
+            backoff: Backoff::new(config.reconnect_backoff, config.reconnect_backoff_max),
+            retry_at: None,
+            throttle: throttle.clone(),
+        };
 
         Ok(Client {
-            client: Arc::new(Mutex::new(client)),
-            api_versions,
+            conn: Arc::new(Mutex::new(conn)),
+            api_versions: Arc::new(RwLock::new(api_versions)),
+            selected: Default::default(),
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight)),
+            timeout: config.request_timeout,
+            rack: config.client_rack.clone(),
+            throttle,
         })
     }
 
-    pub fn server_versions(&self, key: ApiKey) -> (usize, usize) {
-        self.api_versions.get(&key).cloned().unwrap()
+    /// Negotiates the api versions and authenticates, bounded by the request timeout.
+    async fn handshake(io: S, config: &Config) -> crate::Result<(transport::TypedClient<S>, ApiVersions, Option<sasl::Session>)> {
+        let mut client = transport::new(io).await;
+        client.set_client_id(config.client_id.clone());
+        let handshake = async {
+            let mut versions = Self::api_versions(&mut client, API_VERSIONS_MAX, config).await?;
+            if KafkaCode::from(versions.error_code) == KafkaCode::UnsupportedVersion {
+                // The error response of older brokers still lists the versions they support
+                let version = versions.api_keys.iter()
+                    .find(|v| ApiKey::from(v.api_key) == ApiKey::ApiVersions)
+                    .map(|v| (v.max_version.max(0) as usize).min(API_VERSIONS_MAX))
+                    .unwrap_or(0);
+                versions = Self::api_versions(&mut client, version, config).await?;
+            }
+            KafkaError::from_code(versions.error_code, None)?;
+            let api_versions: ApiVersions = versions.api_keys.into_iter().map(|v| {
+                (ApiKey::from(v.api_key), (v.min_version as usize, v.max_version as usize))
+            }).collect();
+
+            let sasl = if config.security.uses_sasl() {
+                let session = sasl::Session::new(config.sasl.clone(), &api_versions)?;
+                session.authenticate(&mut client).await?;
+                Some(session)
+            } else {
+                None
+            };
+            Ok((api_versions, sasl))
+        };
+        let (api_versions, sasl) = time::timeout(config.request_timeout, handshake).await.unwrap_or(Err(Error::Timeout))?;
+        Ok((client, api_versions, sasl))
+    }
+
+    /// Handle to the same connection, whose requests time out after `timeout` instead of [Config::request_timeout].
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Client { timeout, ..self.clone() }
+    }
+
+    /// Sends the request with the highest version supported by both rafka and the broker,
+    /// reconnecting first when the connection broke, and re-authenticating when the SASL session is about to expire.
+    ///
+    /// Fails with [Error::Timeout] when the response does not arrive in time, its late response is then discarded.
+    /// Requests timing out before they were written are not sent at all.
+    pub(crate) fn call<Req>(&self, req: Req) -> impl Future<Output=crate::Result<Req::Response>>
+        where Req: ApiRequest + Send + 'static,
+              Req::Response: Send,
+    {
+        let conn = self.conn.clone();
+        let client = self.clone();
+        let in_flight = self.in_flight.clone();
+        let timeout = self.timeout;
+        // Closed once the caller stopped waiting, requests it gave up on are not sent anymore
+        let (waiting, mut caller) = oneshot::channel::<()>();
+        let send = async move {
+            let response = {
+                let mut conn = conn.lock().await;
+                let ready = conn.ready(&client.api_versions).await?;
+                // Negotiated after reconnecting, since the broker may have been upgraded in the meantime
+                let version = client.api_version::<Req>()?;
+                let ready = ServiceExt::<CallReq<Req>>::ready_and(ready).await?;
+                if let Err(TryRecvError::Closed) = caller.try_recv() {
+                    return Err(Error::Timeout);
+                }
+                ready.call(CallReq::new(version, req))
+            };
+            // Dropping the response abandons its correlation id, so a late response is discarded
+            match future::select(response, caller).await {
+                Either::Left((response, _)) => response,
+                Either::Right(_) => Err(Error::Timeout),
+            }
+        };
+        async move {
+            let call = async move {
+                let _waiting = waiting;
+                let slot = in_flight.acquire_owned().await;
+                // Sent by a separate task, since a caller dropping the request must not interrupt a re-authentication.
+                // The task keeps the slot until the response arrived or the caller gave up on it
+                let task = tokio::spawn(async move {
+                    let _slot = slot;
+                    send.await
+                });
+                match task.await {
+                    Ok(response) => response,
+                    Err(err) => std::panic::resume_unwind(err.into_panic()),
+                }
+            };
+            time::timeout(timeout, call).await.unwrap_or(Err(Error::Timeout))
+        }
+    }
+
+    async fn api_versions(
+        client: &mut transport::TypedClient<S>,
+        version: usize,
+        config: &Config,
+    ) -> crate::Result<api_versions::Response> {
+        let request = api_versions::Request {
+            client_software_name: Some(config.client_software_name.clone()),
+            client_software_version: Some(config.client_software_version.clone()),
+            tags: crate::proto::TagBuffer::default().into(),
+        };
+        let req = CallReq::new(version, request);
+
+        let ready = ServiceExt::<CallReq<api_versions::Request>>::ready_and(client);
+        ready.await?.call(req).await
+    }
+
+    /// Versions of `key` supported by the broker, `None` if it does not support the api.
+    pub fn server_versions(&self, key: ApiKey) -> Option<(usize, usize)> {
+        self.api_versions.read().unwrap().get(&key).cloned()
+    }
+
+    /// Range of versions of `key` supported by both the broker and the client range.
+    pub fn version_match(&self, key: ApiKey, (c_min, c_max): (usize, usize)) -> crate::Result<(usize, usize)> {
+        let broker = self.server_versions(key);
+        broker
+            .map(|(s_min, s_max)| (s_min.max(c_min), s_max.min(c_max)))
+            .filter(|(min, max)| min <= max)
+            .ok_or(Error::UnsupportedVersion { api_key: key, supported: (c_min, c_max), broker })
     }
 
-    pub fn version_match(&self, key: ApiKey, (c_min, c_max): (usize, usize)) -> (usize, usize) {
-        let (s_min, s_max) = self.api_versions.get(&key).cloned().unwrap();
-        (s_min.max(c_max), s_max.min(c_max))
+    /// Highest version of `Req` supported by both rafka and the broker.
+    pub fn api_version<Req: ApiRequest>(&self) -> crate::Result<usize> {
+        let broker = self.server_versions(Req::API_KEY);
+        let version = broker.and_then(|broker| negotiate_version(Req::VERSIONS, broker))
+            .ok_or(Error::UnsupportedVersion { api_key: Req::API_KEY, supported: Req::VERSIONS, broker })?;
+        self.selected.lock().unwrap().insert(Req::API_KEY, version);
+        Ok(version)
     }
+
+    /// Responses of the broker which throttled the client for exceeding a quota,
+    /// requests are held back until the last throttle time elapsed.
+    pub fn throttle_metrics(&self) -> ThrottleMetrics {
+        self.throttle.metrics()
+    }
+
+    /// Versions selected for the requests sent over this connection so far.
+    pub fn selected_versions(&self) -> BTreeMap<ApiKey, usize> {
+        self.selected.lock().unwrap().clone()
+    }
+}
+
+impl<S> Connection<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    /// Transport ready to send a request, re-established if it broke.
+    async fn ready(&mut self, api_versions: &RwLock<ApiVersions>) -> crate::Result<&mut transport::TypedClient<S>> {
+        if let Some(client) = &mut self.client {
+            if let Err(err) = ServiceExt::<()>::ready_and(client).await {
+                self.client = None;
+                if self.connect.is_none() {
+                    return Err(err);
+                }
+            }
+        }
+        if self.client.is_none() {
+            self.reconnect(api_versions).await?;
+        }
+
+        let client = self.client.as_mut().ok_or(Error::Disconnected)?;
+        if let Some(sasl) = &self.sasl {
+            sasl.refresh(client).await?;
+        }
+        Ok(client)
+    }
+
+    /// Opens a new connection once the backoff after the last failed attempt elapsed, and repeats the handshake.
+    async fn reconnect(&mut self, api_versions: &RwLock<ApiVersions>) -> crate::Result<()> {
+        let connect = self.connect.clone().ok_or(Error::Disconnected)?;
+        if let Some(retry_at) = self.retry_at {
+            time::delay_until(retry_at.into()).await;
+        }
+
+        let res = async {
+            let io = connect().await?;
+            Client::handshake(io, &self.config).await
+        };
+        match res.await {
+            Ok((mut client, versions, sasl)) => {
+                client.set_throttle(self.throttle.clone());
+                self.client = Some(client);
+                self.sasl = sasl;
+                self.backoff.reset();
+                self.retry_at = None;
+                *api_versions.write().unwrap() = versions;
+                Ok(())
+            }
+            Err(err) => {
+                self.retry_at = Some(Instant::now() + self.backoff.next());
+                Err(err)
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_from_stream() {
+    use crate::config::{SaslConfig, SecurityConfig};
+    use crate::proto::{sasl_authenticate, sasl_handshake, TagBuffer};
+    use crate::transport::mock;
+
+    // Broker supporting only ApiVersions v2, which requires the client to retry with a lower version
+    let io = mock::spawn(|mut req| match req.header.api_key {
+        ApiKey::ApiVersions if req.header.api_version > 2 => {
+            // Unsupported versions are answered with v0
+            req.header.api_version = 0;
+            let mut res = api_versions::Response::new(&[(ApiKey::ApiVersions, 0, 2)]);
+            res.error_code = KafkaCode::UnsupportedVersion.into();
+            Some(req.respond::<api_versions::Request>(&res))
+        }
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[
+            (ApiKey::ApiVersions, 0, 2),
+            (ApiKey::SaslHandshake, 0, 1),
+            (ApiKey::SaslAuthenticate, 0, 2),
+        ])),
+        ApiKey::SaslHandshake => Some(req.respond::<sasl_handshake::Request>(&sasl_handshake::Response {
+            error_code: 0,
+            mechanisms: vec!["PLAIN".to_string()],
+        })),
+        ApiKey::SaslAuthenticate => Some(req.respond::<sasl_authenticate::Request>(&sasl_authenticate::Response {
+            session_lifetime_ms: Some(0),
+            tags: TagBuffer::default().into(),
+            ..Default::default()
+        })),
+        _ => None,
+    });
+
+    let config = Config {
+        security: SecurityConfig::SaslPlaintext,
+        sasl: SaslConfig {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let client = Client::from_stream(io, &config).await.unwrap();
+    assert_eq!(client.server_versions(ApiKey::ApiVersions), Some((0, 2)));
+    assert!(client.conn.lock().await.sasl.is_some());
+}
+
+#[tokio::test]
+async fn test_version_negotiation() {
+    use crate::proto::{list_offsets, produce};
+    use crate::transport::mock;
+
+    assert_eq!(negotiate_version((1, 5), (0, 3)), Some(3));
+    assert_eq!(negotiate_version((1, 5), (4, 9)), Some(5));
+    assert_eq!(negotiate_version((4, 9), (0, 3)), None);
+
+    let io = mock::spawn(|req| match req.header.api_key {
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[
+            (ApiKey::ApiVersions, 0, 3),
+            (ApiKey::Produce, 0, 2),
+            (ApiKey::ListOffsets, 2, 7),
+        ])),
+        _ => None,
+    });
+    let client = Client::from_stream(io, &Config::default()).await.unwrap();
+
+    assert_eq!(client.version_match(ApiKey::ListOffsets, (0, 4)).unwrap(), (2, 4));
+    assert_eq!(client.api_version::<list_offsets::Request>().unwrap(), 5);
+    assert_eq!(client.selected_versions().into_iter().collect::<Vec<_>>(), vec![(ApiKey::ListOffsets, 5)]);
+
+    // Produce v3 is the oldest version supported by rafka, older versions can't carry v2 record batches
+    match client.call(produce::Request::default()).await {
+        Err(Error::UnsupportedVersion { api_key: ApiKey::Produce, supported: (3, 8), broker: Some((0, 2)) }) => {}
+        res => panic!("Unexpected result {:?}", res.map(|_| ())),
+    }
+    match client.api_version::<crate::proto::fetch::Request>() {
+        Err(err @ Error::UnsupportedVersion { broker: None, .. }) => assert_eq!(err.to_string(), "Broker does not support Fetch"),
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn test_request_timeout() {
+    use crate::proto::metadata;
+    use crate::transport::mock;
+
+    let config = Config {
+        request_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+
+    // The first Metadata request is answered late
+    let mut first = true;
+    let io = mock::spawn_delayed(move |req| match req.header.api_key {
+        ApiKey::ApiVersions => Some((Duration::from_millis(0), mock::api_versions(&req, &[
+            (ApiKey::ApiVersions, 0, 3),
+            (ApiKey::Metadata, 0, 9),
+        ]))),
+        ApiKey::Metadata => {
+            let delay = if std::mem::take(&mut first) { 300 } else { 0 };
+            let res = metadata::Response::new(&[(1, "b1")], &[]);
+            Some((Duration::from_millis(delay), req.respond::<metadata::Request>(&res)))
+        }
+        _ => None,
+    });
+    let client = Client::from_stream(io, &config).await.unwrap();
+    match client.metadata(vec![]).await {
+        Err(Error::Timeout) => {}
+        res => panic!("Unexpected result {:?}", res.map(|_| ())),
+    }
+
+    // The late response is discarded, and the connection stays usable
+    let res = client.with_timeout(Duration::from_secs(5)).metadata(vec![]).await.unwrap();
+    assert_eq!(res.brokers[0].node_id, 1);
+
+    // Brokers which never finish the handshake
+    let io = mock::spawn_delayed(|req| Some((Duration::from_secs(10), mock::api_versions(&req, &[]))));
+    match Client::from_stream(io, &config).await {
+        Err(Error::Timeout) => {}
+        res => panic!("Unexpected result {:?}", res.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn test_abandoned_requests() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use bytes::Bytes;
+    use crate::proto::metadata;
+    use crate::transport::mock;
+
+    let config = Config {
+        request_timeout: Duration::from_millis(50),
+        max_in_flight: 2,
+        ..Default::default()
+    };
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let io = mock::spawn_delayed(move |req| match req.header.api_key {
+        ApiKey::ApiVersions => Some((Duration::from_millis(0), mock::api_versions(&req, &[
+            (ApiKey::ApiVersions, 0, 3),
+            (ApiKey::Metadata, 0, 9),
+        ]))),
+        // The first requests are never answered
+        ApiKey::Metadata if counter.fetch_add(1, Ordering::SeqCst) < 3 => Some((Duration::from_millis(0), Bytes::new())),
+        ApiKey::Metadata => {
+            let res = metadata::Response::new(&[(1, "b1")], &[]);
+            Some((Duration::from_millis(0), req.respond::<metadata::Request>(&res)))
+        }
+        _ => None,
+    });
+    let client = Client::from_stream(io, &config).await.unwrap();
+
+    // Requests timing out while waiting for the connection are not sent
+    let conn = client.conn.lock().await;
+    assert!(matches!(client.metadata(vec![]).await, Err(Error::Timeout)));
+    drop(conn);
+
+    // Slots of unanswered requests are released on timeout, more of them than fit in flight don't block the connection
+    for _ in 0..3 {
+        assert!(matches!(client.metadata(vec![]).await, Err(Error::Timeout)));
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    let res = client.metadata(vec![]).await.unwrap();
+    assert_eq!(res.brokers[0].node_id, 1);
+}
+
+#[tokio::test]
+async fn test_throttle() {
+    use crate::proto::{client_throttle, heartbeat, TagBuffer};
+    use crate::transport::mock;
+
+    // Brokers delay responses of versions from before KIP-219 themselves
+    assert_eq!(client_throttle(1, 2, Some(100)), None);
+    assert_eq!(client_throttle(2, 2, Some(0)), None);
+    assert_eq!(client_throttle(2, 2, Some(100)), Some(Duration::from_millis(100)));
+
+    // The first Heartbeat exceeds the quota
+    let mut first = true;
+    let io = mock::spawn(move |req| match req.header.api_key {
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[
+            (ApiKey::ApiVersions, 0, 3),
+            (ApiKey::Heartbeat, 0, 4),
+        ])),
+        ApiKey::Heartbeat => Some(req.respond::<heartbeat::Request>(&heartbeat::Response {
+            throttle_time_ms: Some(if std::mem::take(&mut first) { 200 } else { 0 }),
+            tags: TagBuffer::default().into(),
+            ..Default::default()
+        })),
+        _ => None,
+    });
+    let client = Client::from_stream(io, &Config::default()).await.unwrap();
+    let heartbeat = || heartbeat::Request {
+        group_instance_id: Some(None),
+        tags: TagBuffer::default().into(),
+        ..Default::default()
+    };
+
+    client.call(heartbeat()).await.unwrap();
+    let throttled_at = Instant::now();
+    let metrics = client.throttle_metrics();
+    assert_eq!(metrics.throttled_responses, 1);
+    assert_eq!(metrics.throttle_time_max, Duration::from_millis(200));
+    assert_eq!(metrics.throttle_time_by_api.get(&ApiKey::Heartbeat), Some(&Duration::from_millis(200)));
+
+    // The next request waits out the throttle time
+    client.call(heartbeat()).await.unwrap();
+    assert!(throttled_at.elapsed() >= Duration::from_millis(150));
+    assert_eq!(client.throttle_metrics().throttled_responses, 1);
+}
+
+#[tokio::test]
+async fn test_reconnect() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::proto::metadata;
+    use crate::transport::mock;
+
+    // The first connection closes on the first Metadata request, the second attempt is refused
+    let connects = Arc::new(AtomicUsize::new(0));
+    let connector = mock::MockConnector({
+        let connects = connects.clone();
+        move |_: &str| {
+            let attempt = connects.fetch_add(1, Ordering::SeqCst);
+            if attempt == 1 {
+                return None;
+            }
+            Some(mock::spawn(move |req| match req.header.api_key {
+                ApiKey::ApiVersions => Some(mock::api_versions(&req, &[
+                    (ApiKey::ApiVersions, 0, 3),
+                    (ApiKey::Metadata, 0, 9),
+                ])),
+                ApiKey::Metadata if attempt > 0 => {
+                    Some(req.respond::<metadata::Request>(&metadata::Response::new(&[(1, "b1")], &[])))
+                }
+                _ => None,
+            }))
+        }
+    });
+    let config = Config {
+        reconnect_backoff: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let client = Client::connect_via(&connector, "b1:9092", &config).await.unwrap();
+
+    let err = client.metadata(vec![]).await.unwrap_err();
+    assert!(err.is_retriable(), "{}", err);
+    let err = client.metadata(vec![]).await.unwrap_err();
+    assert!(matches!(err, Error::Io(_)) && err.is_retriable(), "{}", err);
+
+    // Waits for the backoff after the refused attempt
+    let start = Instant::now();
+    assert_eq!(client.metadata(vec![]).await.unwrap().brokers.len(), 1);
+    assert!(start.elapsed() >= Duration::from_millis(80));
+    assert_eq!(connects.load(Ordering::SeqCst), 3);
+
+    // Connections over a single stream stay broken
+    let io = mock::spawn(|req| match req.header.api_key {
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3), (ApiKey::Metadata, 0, 9)])),
+        _ => None,
+    });
+    let client = Client::from_stream(io, &Config::default()).await.unwrap();
+    assert!(client.metadata(vec![]).await.unwrap_err().is_retriable());
+    assert!(client.metadata(vec![]).await.is_err());
 }
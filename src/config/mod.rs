@@ -0,0 +1,242 @@
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::sasl::TokenProvider;
+
+mod properties;
+
+#[derive(Debug, Clone, Default)]
+pub enum SecurityConfig {
+    #[default]
+    Unsecured,
+    SSL,
+    /// SASL authentication over a plain connection, configured by [SaslConfig].
+    SaslPlaintext,
+    /// SASL authentication over TLS.
+    SaslSsl,
+}
+
+impl SecurityConfig {
+    pub fn uses_tls(&self) -> bool {
+        matches!(self, SecurityConfig::SSL | SecurityConfig::SaslSsl)
+    }
+
+    pub fn uses_sasl(&self) -> bool {
+        matches!(self, SecurityConfig::SaslPlaintext | SecurityConfig::SaslSsl)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SaslMechanism {
+    #[default]
+    Plain,
+    ScramSha256,
+    ScramSha512,
+    /// Bearer tokens supplied by [SaslConfig::token_provider].
+    OAuthBearer,
+}
+
+impl SaslMechanism {
+    /// Name of the mechanism, as sent in the `SaslHandshake` request.
+    pub fn name(self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+            SaslMechanism::OAuthBearer => "OAUTHBEARER",
+        }
+    }
+
+    /// Mechanism with the given name, like `SCRAM-SHA-256`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [SaslMechanism::Plain, SaslMechanism::ScramSha256, SaslMechanism::ScramSha512, SaslMechanism::OAuthBearer]
+            .iter()
+            .copied()
+            .find(|mechanism| mechanism.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// SASL settings, used when [SecurityConfig::SaslPlaintext] or [SecurityConfig::SaslSsl] is selected.
+#[derive(Clone, Default)]
+pub struct SaslConfig {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: String,
+    /// Source of tokens for [SaslMechanism::OAuthBearer], which ignores the username and password.
+    pub token_provider: Option<Arc<dyn TokenProvider>>,
+}
+
+impl std::fmt::Debug for SaslConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SaslConfig")
+            .field("mechanism", &self.mechanism)
+            .field("username", &self.username)
+            .field("password", &"[redacted]")
+            .field("token_provider", &self.token_provider.as_ref().map(|_| "[provider]"))
+            .finish()
+    }
+}
+
+/// TLS settings, used when [SecurityConfig::SSL] is selected.
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM file with the trusted CA certificates, the Mozilla root certificates are trusted when not set.
+    pub ca_location: Option<PathBuf>,
+    /// PEM file with the client certificate chain, presented to brokers which require client authentication.
+    pub certificate_location: Option<PathBuf>,
+    /// PEM file with the PKCS#8 or RSA private key of the client certificate.
+    pub key_location: Option<PathBuf>,
+    /// Name sent in SNI and verified against the broker certificate, instead of the host being connected to.
+    pub server_name: Option<String>,
+    /// Accepts broker certificates issued for any name, the certificate chain is still verified.
+    ///
+    /// Only meant for test clusters, where certificates don't match the advertised listeners.
+    pub skip_hostname_verification: bool,
+}
+
+/// Socket options of TCP connections to the brokers.
+#[derive(Debug, Clone)]
+pub struct SocketConfig {
+    /// Disables Nagle's algorithm, so small requests are sent right away.
+    pub nodelay: bool,
+    /// `SO_SNDBUF`, the system default when not set.
+    pub send_buffer_size: Option<usize>,
+    /// `SO_RCVBUF`, the system default when not set.
+    pub receive_buffer_size: Option<usize>,
+    /// Idle time before TCP keepalive probes are sent, keepalive is disabled when not set.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        SocketConfig {
+            nodelay: true,
+            send_buffer_size: None,
+            receive_buffer_size: None,
+            keepalive: None,
+        }
+    }
+}
+
+/// Default of [Config::client_id].
+pub const DEFAULT_CLIENT_ID: &str = "rafka";
+
+/// Default of [Config::max_in_flight], matching `max.in.flight.requests.per.connection` of the Java client.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 5;
+
+/// Default of [Config::metadata_max_age], matching `metadata.max.age.ms` of the Java client.
+pub const DEFAULT_METADATA_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Default of [Config::reconnect_backoff], matching `reconnect.backoff.ms` of the Java client.
+pub const DEFAULT_RECONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Default of [Config::reconnect_backoff_max], matching `reconnect.backoff.max.ms` of the Java client.
+pub const DEFAULT_RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// Default of [Config::request_timeout], matching `request.timeout.ms` of the Java client.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Servers a [Cluster](crate::client::Cluster) discovers the brokers from, in the `host:port` form.
+    pub bootstrap_servers: Vec<String>,
+    /// Sent in the header of every request, brokers use it in logs, metrics and quotas.
+    pub client_id: String,
+    /// Name of the client software reported to brokers (KIP-511), may only contain alphanumerics, `-` and `.`.
+    pub client_software_name: String,
+    /// Version of the client software reported to brokers, with the same restrictions as the name.
+    pub client_software_version: String,
+    /// Rack of the client, sent in requests of [Client::fetch](crate::client::Client::fetch) so brokers can serve them
+    /// from the closest replica (KIP-392), which requires Fetch v11.
+    pub client_rack: Option<String>,
+    pub security: SecurityConfig,
+    pub sasl: SaslConfig,
+    #[cfg(feature = "tls")]
+    pub tls: TlsConfig,
+    /// Interval of the cluster metadata refresh, which also happens after errors caused by stale metadata.
+    pub metadata_max_age: Duration,
+    /// How long a request waits for its response before failing with [Error::Timeout],
+    /// overridable per request with [Client::with_timeout].
+    ///
+    /// [Error::Timeout]: crate::Error::Timeout
+    /// [Client::with_timeout]: crate::client::Client::with_timeout
+    pub request_timeout: Duration,
+    /// Delay before reconnecting after a failed attempt, doubled after each consecutive failure.
+    pub reconnect_backoff: Duration,
+    /// Upper bound of the reconnection delay.
+    pub reconnect_backoff_max: Duration,
+    /// Requests sent over a connection without waiting for their responses, further requests wait for a slot.
+    pub max_in_flight: usize,
+    pub socket: SocketConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bootstrap_servers: vec![],
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            client_software_name: "rafka".to_string(),
+            client_software_version: env!("CARGO_PKG_VERSION").to_string(),
+            client_rack: None,
+            security: Default::default(),
+            sasl: Default::default(),
+            #[cfg(feature = "tls")]
+            tls: Default::default(),
+            metadata_max_age: DEFAULT_METADATA_MAX_AGE,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            reconnect_backoff: DEFAULT_RECONNECT_BACKOFF,
+            reconnect_backoff_max: DEFAULT_RECONNECT_BACKOFF_MAX,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            socket: Default::default(),
+        }
+    }
+}
+
+/// Whether `s` is accepted by brokers as a software name or version, matching `[a-zA-Z0-9](?:[a-zA-Z0-9\-.]*[a-zA-Z0-9])?`.
+fn valid_software_id(s: &str) -> bool {
+    let alphanumeric = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    alphanumeric(s.chars().next())
+        && alphanumeric(s.chars().last())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+impl Config {
+    /// Checks settings which would otherwise only fail once connected, or not at all.
+    pub fn validate(&self) -> crate::Result<()> {
+        let invalid = |msg: String| Err(crate::Error::Config(msg));
+        if !valid_software_id(&self.client_software_name) {
+            return invalid(format!("Invalid client software name {:?}", self.client_software_name));
+        }
+        if !valid_software_id(&self.client_software_version) {
+            return invalid(format!("Invalid client software version {:?}", self.client_software_version));
+        }
+        if self.max_in_flight == 0 {
+            return invalid("At least one request must be allowed in flight".to_string());
+        }
+        if self.reconnect_backoff > self.reconnect_backoff_max {
+            return invalid("The reconnect backoff exceeds its maximum".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_validate() {
+    assert!(Config::default().validate().is_ok());
+
+    let config = |name: &str, version: &str| Config {
+        client_software_name: name.to_string(),
+        client_software_version: version.to_string(),
+        ..Default::default()
+    };
+    assert!(config("my-app.client", "1.0.0-rc1").validate().is_ok());
+    assert!(config("my app", "1.0").validate().is_err());
+    assert!(config("rafka", "1.0-").validate().is_err());
+    assert!(config("rafka", "").validate().is_err());
+
+    let err = Config { max_in_flight: 0, ..Default::default() }.validate().unwrap_err();
+    assert_eq!(err.to_string(), "Invalid configuration: At least one request must be allowed in flight");
+}
@@ -0,0 +1,385 @@
+//! Settings named like those of the Java client, loaded from `.properties` files and environment variables,
+//! so the same configuration can be shared with JVM services.
+use std::path::Path;
+use std::time::Duration;
+use std::{fs, io};
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
+
+use super::{Config, SaslMechanism, SecurityConfig};
+use crate::Error;
+
+impl Config {
+    /// Applies a setting named like in the Java client, such as `request.timeout.ms`.
+    ///
+    /// Returns `false` for keys rafka does not support, and fails naming the key when the value is invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> crate::Result<bool> {
+        let invalid = |reason: &str| Error::Config(format!("Invalid value {:?} of {}: {}", value, key, reason));
+        let ms = || value.trim().parse().map(Duration::from_millis).map_err(|_| invalid("expected milliseconds"));
+        let bytes = || match value.trim().parse::<i64>() {
+            Ok(-1) => Ok(None),
+            Ok(size) if size > 0 => Ok(Some(size as usize)),
+            _ => Err(invalid("expected a size in bytes, or -1 for the system default")),
+        };
+        // Java key stores can't be read, the PEM type holds the certificate chain and the key in the same file
+        #[cfg(feature = "tls")]
+        let pem_store = || {
+            let path = PathBuf::from(value.trim());
+            match path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase).as_deref() {
+                Some("jks") | Some("p12") | Some("pfx") => Err(invalid("only PEM stores are supported")),
+                _ => Ok(path),
+            }
+        };
+
+        match key {
+            "bootstrap.servers" => {
+                self.bootstrap_servers = value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect();
+            }
+            "client.id" => self.client_id = value.to_string(),
+            "client.rack" => self.client_rack = Some(value.to_string()).filter(|rack| !rack.is_empty()),
+            "security.protocol" => {
+                self.security = match value.trim().to_ascii_uppercase().as_str() {
+                    "PLAINTEXT" => SecurityConfig::Unsecured,
+                    "SSL" => SecurityConfig::SSL,
+                    "SASL_PLAINTEXT" => SecurityConfig::SaslPlaintext,
+                    "SASL_SSL" => SecurityConfig::SaslSsl,
+                    _ => return Err(invalid("expected PLAINTEXT, SSL, SASL_PLAINTEXT or SASL_SSL")),
+                }
+            }
+            "sasl.mechanism" => {
+                self.sasl.mechanism = SaslMechanism::from_name(value.trim())
+                    .ok_or_else(|| invalid("expected PLAIN, SCRAM-SHA-256, SCRAM-SHA-512 or OAUTHBEARER"))?;
+            }
+            "sasl.username" => self.sasl.username = value.to_string(),
+            "sasl.password" => self.sasl.password = value.to_string(),
+            "sasl.jaas.config" => {
+                for (name, value) in jaas_options(value) {
+                    match name.as_str() {
+                        "username" => self.sasl.username = value,
+                        "password" => self.sasl.password = value,
+                        _ => {}
+                    }
+                }
+            }
+            #[cfg(feature = "tls")]
+            "ssl.truststore.location" | "ssl.ca.location" => self.tls.ca_location = Some(pem_store()?),
+            #[cfg(feature = "tls")]
+            "ssl.keystore.location" => {
+                let path = pem_store()?;
+                self.tls.certificate_location = Some(path.clone());
+                self.tls.key_location = Some(path);
+            }
+            #[cfg(feature = "tls")]
+            "ssl.truststore.type" | "ssl.keystore.type" => {
+                if !value.trim().eq_ignore_ascii_case("PEM") {
+                    return Err(invalid("only PEM stores are supported"));
+                }
+            }
+            #[cfg(feature = "tls")]
+            "ssl.certificate.location" => self.tls.certificate_location = Some(PathBuf::from(value.trim())),
+            #[cfg(feature = "tls")]
+            "ssl.key.location" => self.tls.key_location = Some(PathBuf::from(value.trim())),
+            #[cfg(feature = "tls")]
+            "ssl.endpoint.identification.algorithm" => {
+                self.tls.skip_hostname_verification = match value.trim().to_ascii_lowercase().as_str() {
+                    "" => true,
+                    "https" => false,
+                    _ => return Err(invalid("expected https, or an empty value to disable hostname verification")),
+                }
+            }
+            "request.timeout.ms" => self.request_timeout = ms()?,
+            "metadata.max.age.ms" => self.metadata_max_age = ms()?,
+            "reconnect.backoff.ms" => self.reconnect_backoff = ms()?,
+            "reconnect.backoff.max.ms" => self.reconnect_backoff_max = ms()?,
+            "max.in.flight.requests.per.connection" => {
+                self.max_in_flight = value.trim().parse().ok().filter(|n| *n > 0)
+                    .ok_or_else(|| invalid("expected a positive number"))?;
+            }
+            "send.buffer.bytes" => self.socket.send_buffer_size = bytes()?,
+            "receive.buffer.bytes" => self.socket.receive_buffer_size = bytes()?,
+            "socket.nagle.disable" => {
+                self.socket.nodelay = value.trim().parse().map_err(|_| invalid("expected true or false"))?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Applies the settings of a Java properties file, returning warnings about the keys rafka does not support.
+    pub fn load_properties(&mut self, text: &str) -> crate::Result<Vec<String>> {
+        let mut warnings = vec![];
+        for (key, value) in parse(text)? {
+            if !self.set(&key, &value)? {
+                warnings.push(format!("Unknown property {}", key));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Reads the properties file at `path`, see [Config::load_properties].
+    pub fn load_properties_file(&mut self, path: impl AsRef<Path>) -> crate::Result<Vec<String>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Reading {}: {}", path.display(), e)))?;
+        self.load_properties(&text)
+    }
+
+    /// Applies settings from `KAFKA_*` and `RAFKA_*` environment variables, `RAFKA_*` taking precedence,
+    /// returning warnings about the keys rafka does not support.
+    ///
+    /// Variables map to keys like in the Confluent images: the prefix is dropped and the name lowercased,
+    /// `_` becomes `.`, `__` becomes `_` and `___` becomes `-`. `KAFKA_REQUEST_TIMEOUT_MS` sets `request.timeout.ms`.
+    pub fn load_env(&mut self) -> crate::Result<Vec<String>> {
+        self.load_vars(std::env::vars())
+    }
+
+    fn load_vars(&mut self, vars: impl IntoIterator<Item=(String, String)>) -> crate::Result<Vec<String>> {
+        let mut vars = vars.into_iter().filter_map(|(name, value)| {
+            let (priority, key) = match (name.strip_prefix("KAFKA_"), name.strip_prefix("RAFKA_")) {
+                (Some(key), _) => (0, env_key(key)),
+                (_, Some(key)) => (1, env_key(key)),
+                _ => return None,
+            };
+            Some((priority, key, name, value))
+        }).collect::<Vec<_>>();
+        vars.sort_by_key(|(priority, ..)| *priority);
+
+        let mut warnings = vec![];
+        for (_, key, name, value) in vars {
+            let known = self.set(&key, &value).map_err(|e| match e {
+                Error::Config(msg) => Error::Config(format!("{}, set by {}", msg, name)),
+                e => e,
+            })?;
+            if !known {
+                warnings.push(format!("Unknown property {}, set by {}", key, name));
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+fn env_key(name: &str) -> String {
+    name.to_ascii_lowercase().replace("___", "-").replace("__", "\0").replace('_', ".").replace('\0', "_")
+}
+
+/// Options of a JAAS login module configuration, like `username` in
+/// `org.apache.kafka.common.security.plain.PlainLoginModule required username="alice" password="secret";`.
+fn jaas_options(config: &str) -> Vec<(String, String)> {
+    let mut res = vec![];
+    let mut rest = config;
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].rsplit(char::is_whitespace).next().unwrap_or_default();
+        let after = &rest[eq + 1..];
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+            }
+            None => {
+                let end = after.find(|c: char| c.is_whitespace() || c == ';').unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        res.push((name.to_string(), value.to_string()));
+        rest = remaining;
+    }
+    res
+}
+
+/// Parses the key-value pairs of a Java properties file, in order.
+///
+/// Keys are separated from values by `=`, `:` or whitespace, lines starting with `#` or `!` are comments,
+/// and lines ending with a backslash continue on the next line.
+fn parse(text: &str) -> crate::Result<Vec<(String, String)>> {
+    let mut res = vec![];
+    let mut lines = text.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let mut line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        // Leading whitespace of continuation lines is ignored
+        let mut logical = String::new();
+        loop {
+            let backslashes = line.len() - line.trim_end_matches('\\').len();
+            if backslashes % 2 == 0 {
+                logical.push_str(line);
+                break;
+            }
+            logical.push_str(&line[..line.len() - 1]);
+            match lines.next() {
+                Some((_, next)) => line = next.trim_start(),
+                None => break,
+            }
+        }
+        let pair = split(&logical).map_err(|msg| Error::Config(format!("Line {}: {}", number + 1, msg)))?;
+        res.push(pair);
+    }
+    Ok(res)
+}
+
+/// Splits a logical line into its unescaped key and value.
+fn split(line: &str) -> Result<(String, String), String> {
+    let mut chars = line.chars().peekable();
+    let mut key = String::new();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '=' | ':' => break,
+            c if c.is_whitespace() => break,
+            '\\' => {
+                chars.next();
+                key.push(unescape(&mut chars)?);
+            }
+            c => {
+                chars.next();
+                key.push(c);
+            }
+        }
+    }
+
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.next_if(|c| *c == '=' || *c == ':');
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(unescape(&mut chars)?),
+            c => value.push(c),
+        }
+    }
+    Ok((key, value))
+}
+
+fn unescape(chars: &mut impl Iterator<Item=char>) -> Result<char, String> {
+    Ok(match chars.next() {
+        Some('t') => '\t',
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('f') => '\x0c',
+        Some('u') => {
+            let hex = chars.take(4).collect::<String>();
+            Some(&hex).filter(|hex| hex.len() == 4)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("Invalid escape \\u{}", hex))?
+        }
+        Some(c) => c,
+        None => '\\',
+    })
+}
+
+#[test]
+fn test_parse_properties() {
+    let text = "# Comment\n\
+        ! Another comment\n\
+        \n\
+        a=1\n\
+        b : 2\n\
+        c 3\n\
+        d=\n\
+        key\\ with\\=escapes = tab\\tand \\u00e9\n\
+        list = one, \\\n\
+        \x20   two\n\
+        path=C:\\\\kafka\\\\\n\
+        \x20 spaced = value with trailing space \n";
+    let pairs = parse(text).unwrap();
+    let pairs = pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>();
+    assert_eq!(pairs, vec![
+        ("a", "1"),
+        ("b", "2"),
+        ("c", "3"),
+        ("d", ""),
+        ("key with=escapes", "tab\tand é"),
+        ("list", "one, two"),
+        ("path", "C:\\kafka\\"),
+        ("spaced", "value with trailing space "),
+    ]);
+
+    let err = parse("a=1\nb=\\u00zz").unwrap_err();
+    assert_eq!(err.to_string(), "Invalid configuration: Line 2: Invalid escape \\u00zz");
+}
+
+#[test]
+fn test_load_properties() {
+    let text = r#"
+        bootstrap.servers=b1:9092, b2:9092
+        client.id=billing
+        security.protocol=SASL_SSL
+        sasl.mechanism=SCRAM-SHA-512
+        sasl.jaas.config=org.apache.kafka.common.security.scram.ScramLoginModule required \
+            username="alice" password="s3cr=t";
+        request.timeout.ms=5000
+        max.in.flight.requests.per.connection=1
+        send.buffer.bytes=-1
+        receive.buffer.bytes=65536
+        acks=all
+        linger.ms=5
+    "#;
+    let mut config = Config::default();
+    let warnings = config.load_properties(text).unwrap();
+    assert_eq!(warnings, vec!["Unknown property acks", "Unknown property linger.ms"]);
+
+    assert_eq!(config.bootstrap_servers, vec!["b1:9092", "b2:9092"]);
+    assert_eq!(config.client_id, "billing");
+    assert!(matches!(config.security, SecurityConfig::SaslSsl));
+    assert_eq!(config.sasl.mechanism, SaslMechanism::ScramSha512);
+    assert_eq!((config.sasl.username.as_str(), config.sasl.password.as_str()), ("alice", "s3cr=t"));
+    assert_eq!(config.request_timeout, Duration::from_secs(5));
+    assert_eq!(config.max_in_flight, 1);
+    assert_eq!(config.socket.send_buffer_size, None);
+    assert_eq!(config.socket.receive_buffer_size, Some(65536));
+
+    let err = Config::default().load_properties("request.timeout.ms=soon").unwrap_err();
+    assert_eq!(err.to_string(), "Invalid configuration: Invalid value \"soon\" of request.timeout.ms: expected milliseconds");
+    let err = Config::default().load_properties("security.protocol=TLS").unwrap_err();
+    assert!(err.to_string().contains("of security.protocol"), "{}", err);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_load_tls_properties() {
+    let mut config = Config::default();
+    let warnings = config.load_properties("\
+        ssl.truststore.location=/etc/kafka/ca.pem\n\
+        ssl.truststore.type=PEM\n\
+        ssl.keystore.location=/etc/kafka/client.pem\n\
+        ssl.endpoint.identification.algorithm=\n").unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(config.tls.ca_location, Some(PathBuf::from("/etc/kafka/ca.pem")));
+    assert_eq!(config.tls.key_location, Some(PathBuf::from("/etc/kafka/client.pem")));
+    assert!(config.tls.skip_hostname_verification);
+
+    let err = config.load_properties("ssl.truststore.type=JKS").unwrap_err();
+    assert!(err.to_string().contains("ssl.truststore.type"), "{}", err);
+    let err = config.load_properties("ssl.keystore.location=/etc/kafka/client.keystore.JKS").unwrap_err();
+    assert!(err.to_string().contains("of ssl.keystore.location: only PEM stores"), "{}", err);
+    let err = config.load_properties("ssl.truststore.location=/etc/kafka/ca.p12").unwrap_err();
+    assert!(err.to_string().contains("of ssl.truststore.location"), "{}", err);
+    assert_eq!(config.tls.ca_location, Some(PathBuf::from("/etc/kafka/ca.pem")));
+}
+
+#[test]
+fn test_load_env() {
+    let vars = vec![
+        ("RAFKA_CLIENT_ID", "rafka-app"),
+        ("KAFKA_CLIENT_ID", "jvm-app"),
+        ("KAFKA_BOOTSTRAP_SERVERS", "b1:9092"),
+        ("KAFKA_SASL_MECHANISM", "PLAIN"),
+        ("KAFKA_MAX_IN_FLIGHT_REQUESTS_PER_CONNECTION", "2"),
+        ("KAFKA_HEAP_OPTS", "-Xmx1G"),
+        ("PATH", "/usr/bin"),
+    ];
+    let vars = vars.into_iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    let mut config = Config::default();
+    let warnings = config.load_vars(vars).unwrap();
+    assert_eq!(warnings, vec!["Unknown property heap.opts, set by KAFKA_HEAP_OPTS"]);
+    assert_eq!(config.client_id, "rafka-app");
+    assert_eq!(config.bootstrap_servers, vec!["b1:9092"]);
+    assert_eq!(config.max_in_flight, 2);
+    assert_eq!(env_key("SSL___VERSION__X_Y"), "ssl-version_x.y");
+
+    let vars = vec![("RAFKA_RECONNECT_BACKOFF_MS".to_string(), "-1".to_string())];
+    let err = Config::default().load_vars(vars).unwrap_err();
+    assert!(err.to_string().ends_with("expected milliseconds, set by RAFKA_RECONNECT_BACKOFF_MS"), "{}", err);
+}
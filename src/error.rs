@@ -0,0 +1,118 @@
+use std::fmt;
+use std::io;
+
+use crate::proto::{self, ApiKey};
+use crate::sasl::SaslError;
+use crate::{KafkaCode, KafkaError};
+
+/// Errors returned by the transport and the clients.
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting to the broker, or reading and writing the connection failed.
+    Io(io::Error),
+    /// The broker sent an invalid frame, such as one exceeding the maximum response size.
+    Framing(String),
+    /// A response could not be decoded.
+    Decode(proto::Error),
+    /// The broker answered with an error code.
+    Kafka(KafkaError),
+    /// The broker did not answer in time.
+    Timeout,
+    /// The connection was closed while requests were in flight.
+    Disconnected,
+    /// The broker supports none of the versions of the request supported by rafka.
+    UnsupportedVersion {
+        api_key: ApiKey,
+        supported: (usize, usize),
+        /// Versions supported by the broker, `None` if it does not support the api at all.
+        broker: Option<(usize, usize)>,
+    },
+    /// Authenticating the connection failed.
+    Sasl(SaslError),
+    /// The configuration is invalid, like an empty list of bootstrap servers.
+    Config(String),
+    /// The request can't be encoded, like a record batch with a compression codec which is not compiled in.
+    Encode(String),
+}
+
+impl Error {
+    /// Error code sent by the broker, if this is a broker error.
+    pub fn code(&self) -> Option<KafkaCode> {
+        match self {
+            Error::Kafka(err) => Some(err.code),
+            Error::Sasl(SaslError::Failed(err)) => Some(err.code),
+            _ => None,
+        }
+    }
+
+    /// Whether the request may succeed when retried, like after the connection broke and was re-established.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Error::Io(_) | Error::Timeout | Error::Disconnected => true,
+            Error::Kafka(err) => err.is_retriable(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Framing(msg) => write!(f, "Invalid frame: {}", msg),
+            Error::Decode(err) => write!(f, "{}", err),
+            Error::Kafka(err) => write!(f, "{}", err),
+            Error::Timeout => write!(f, "Request timed out"),
+            Error::Disconnected => write!(f, "Connection closed with requests in flight"),
+            Error::UnsupportedVersion { api_key, supported: (min, max), broker: Some((b_min, b_max)) } => write!(
+                f, "Broker supports {:?} versions {}..={}, rafka supports {}..={}", api_key, b_min, b_max, min, max
+            ),
+            Error::UnsupportedVersion { api_key, broker: None, .. } => write!(f, "Broker does not support {:?}", api_key),
+            Error::Sasl(err) => write!(f, "{}", err),
+            Error::Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            Error::Encode(msg) => write!(f, "Invalid request: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Decode(err) => Some(err),
+            Error::Kafka(err) => Some(err),
+            Error::Sasl(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<proto::Error> for Error {
+    fn from(err: proto::Error) -> Self {
+        Error::Decode(err)
+    }
+}
+
+impl From<KafkaError> for Error {
+    fn from(err: KafkaError) -> Self {
+        Error::Kafka(err)
+    }
+}
+
+impl From<KafkaCode> for Error {
+    fn from(code: KafkaCode) -> Self {
+        Error::Kafka(code.into())
+    }
+}
+
+impl From<SaslError> for Error {
+    fn from(err: SaslError) -> Self {
+        Error::Sasl(err)
+    }
+}
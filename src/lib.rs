@@ -1,4 +1,3 @@
-#![feature(min_const_generics)]
 #![allow(unused_variables, unused_imports, dead_code)]
 
 #[macro_use]
@@ -10,10 +9,14 @@ pub mod proto;
 pub mod transport;
 pub mod client;
 pub mod config;
+pub mod sasl;
+mod error;
 
-pub type Result<T, E = anyhow::Error> = anyhow::Result<T, E>;
+pub use error::Error;
 
-#[derive(Clone, Copy, Debug, Hash)]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Wired)]
 #[repr(i16)]
 pub enum KafkaCode {
     /// The server experienced an unexpected error when processing the request.
@@ -194,12 +197,170 @@ pub enum KafkaCode {
     InvalidRecord = 87,
     /// There are unstable offsets that need to be cleared.
     UnstableOffsetCommit = 88,
+    /// The throttling quota has been exceeded.
+    ThrottlingQuotaExceeded = 89,
+    /// There is a newer producer with the same transactionalId which fences the current one.
+    ProducerFenced = 90,
+    /// A request illegally referred to a resource that does not exist.
+    ResourceNotFound = 91,
+    /// A request illegally referred to the same resource twice.
+    DuplicateResource = 92,
+    /// Requested credential would not meet criteria for acceptability.
+    UnacceptableCredential = 93,
+    /// Indicates that the either the sender or recipient of a voter-only request is not one of the expected voters.
+    InconsistentVoterSet = 94,
+    /// The given update version was invalid.
+    InvalidUpdateVersion = 95,
+    /// Unable to update finalized features due to an unexpected server error.
+    FeatureUpdateFailed = 96,
+    /// Request principal deserialization failed during forwarding. This indicates an internal error on the broker cluster security setup.
+    PrincipalDeserializationFailure = 97,
+    /// Requested snapshot was not found.
+    SnapshotNotFound = 98,
+    /// Requested position is not greater than or equal to zero, and less than the size of the snapshot.
+    PositionOutOfRange = 99,
+    /// This server does not host this topic ID.
+    UnknownTopicId = 100,
+    /// This broker ID is already in use.
+    DuplicateBrokerRegistration = 101,
+    /// The given broker ID was not registered.
+    BrokerIdNotRegistered = 102,
+    /// The log's topic ID did not match the topic ID in the request.
+    InconsistentTopicId = 103,
+    /// The clusterId in the request does not match that found on the server.
+    InconsistentClusterId = 104,
+    /// The transactionalId could not be found.
+    TransactionalIdNotFound = 105,
+    /// The fetch session encountered inconsistent topic ID usage.
+    FetchSessionTopicIdError = 106,
+    /// The new ISR contains at least one ineligible replica.
+    IneligibleReplica = 107,
+    /// The AlterPartition request successfully updated the partition state but the leader has changed.
+    NewLeaderElected = 108,
+    /// The requested offset is moved to tiered storage.
+    OffsetMovedToTieredStorage = 109,
+    /// The member epoch is fenced by the group coordinator. The member must abandon all its partitions and rejoin.
+    FencedMemberEpoch = 110,
+    /// The instance ID is still used by another member in the consumer group. That member must leave first.
+    UnreleasedInstanceId = 111,
+    /// The assignor or its version range is not supported by the consumer group.
+    UnsupportedAssignor = 112,
+    /// The member epoch is stale. The member must retry after receiving its updated member epoch via the ConsumerGroupHeartbeat API.
+    StaleMemberEpoch = 113,
+    /// The request was sent to an endpoint of the wrong type.
+    MismatchedEndpointType = 114,
+    /// This endpoint type is not supported yet.
+    UnsupportedEndpointType = 115,
+    /// This controller ID is not known.
+    UnknownControllerId = 116,
+    /// Client sent a push telemetry request with an invalid or outdated subscription ID.
+    UnknownSubscriptionId = 117,
+    /// Client sent a push telemetry request larger than the maximum size the broker will accept.
+    TelemetryTooLarge = 118,
+    /// The controller has considered the broker registration to be invalid.
+    InvalidRegistration = 119,
+    /// The server encountered an error with the transaction. The client can abort the transaction to continue using this transactional ID.
+    TransactionAbortable = 120,
+    /// The record state is invalid. The acknowledgement of delivery could not be completed.
+    InvalidRecordState = 121,
+    /// The share session was not found.
+    ShareSessionNotFound = 122,
+    /// The share session epoch is invalid.
+    InvalidShareSessionEpoch = 123,
+    /// The share coordinator rejected the request because the share-group state epoch did not match.
+    FencedStateEpoch = 124,
+    /// The voter key doesn't match the receiving replica's key.
+    InvalidVoterKey = 125,
+    /// The voter is already part of the set of voters.
+    DuplicateVoter = 126,
+    /// The voter is not part of the set of voters.
+    VoterNotFound = 127,
+    /// The regular expression is not valid.
+    InvalidRegularExpression = 128,
+    /// Client metadata is stale. The client should rebootstrap to obtain new metadata.
+    RebootstrapRequired = 129,
+    /// Error code which is not known to this crate.
+    Unknown(i16),
 }
 
+impl KafkaCode {
+    /// Whether the request may succeed when retried, possibly after [Self::requires_metadata_refresh].
+    pub fn is_retriable(self) -> bool {
+        use KafkaCode::*;
+        matches!(self,
+            CorruptMessage | UnknownTopicOrPartition | LeaderNotAvailable | NotLeaderOrFollower | RequestTimedOut
+            | ReplicaNotAvailable | NetworkException | CoordinatorLoadInProgress | CoordinatorNotAvailable
+            | NotCoordinator | NotEnoughReplicas | NotEnoughReplicasAfterAppend | NotController
+            | ConcurrentTransactions | KafkaStorageError | FetchSessionIdNotFound | InvalidFetchSessionEpoch
+            | ListenerNotFound | FencedLeaderEpoch | UnknownLeaderEpoch | OffsetNotAvailable
+            | PreferredLeaderNotAvailable | EligibleLeadersNotAvailable | UnstableOffsetCommit
+            | ThrottlingQuotaExceeded | UnknownTopicId | InconsistentTopicId | FetchSessionTopicIdError
+        )
+    }
+
+    /// Whether the error indicates stale cluster metadata, like a moved partition leader,
+    /// which has to be refreshed before retrying.
+    pub fn requires_metadata_refresh(self) -> bool {
+        use KafkaCode::*;
+        matches!(self,
+            UnknownTopicOrPartition | LeaderNotAvailable | NotLeaderOrFollower | ReplicaNotAvailable
+            | NetworkException | KafkaStorageError | ListenerNotFound | FencedLeaderEpoch
+            | UnknownTopicId | InconsistentTopicId | RebootstrapRequired
+        )
+    }
+}
+
+impl std::fmt::Display for KafkaCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KafkaCode::Unknown(code) => write!(f, "Unknown error code {}", code),
+            code => write!(f, "{:?} ({})", code, i16::from(*code)),
+        }
+    }
+}
+
+/// Error code returned by the broker, with the error message if the response carries one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KafkaError {
+    pub code: KafkaCode,
+    pub message: Option<String>,
+}
+
+impl KafkaError {
+    /// Converts a response error code into a result, 0 signals success.
+    pub fn from_code(code: i16, message: Option<&str>) -> Result<(), KafkaError> {
+        res_from_code(code).map_err(|code| KafkaError { code, message: message.map(ToString::to_string) })
+    }
+
+    pub fn is_retriable(&self) -> bool {
+        self.code.is_retriable()
+    }
+
+    pub fn requires_metadata_refresh(&self) -> bool {
+        self.code.requires_metadata_refresh()
+    }
+}
+
+impl From<KafkaCode> for KafkaError {
+    fn from(code: KafkaCode) -> Self {
+        Self { code, message: None }
+    }
+}
+
+impl std::fmt::Display for KafkaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.code, message),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for KafkaError {}
+
 fn res_from_code(code: i16) -> Result<(), KafkaCode> {
     match code {
         0 => Ok(()),
-        1..=88 => unsafe { Err(std::mem::transmute(code)) }
-        e => Err(KafkaCode::UnknownServerError),
+        code => Err(KafkaCode::from(code)),
     }
 }
\ No newline at end of file
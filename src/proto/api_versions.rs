@@ -1,42 +1,91 @@
-use crate::proto::{ApiKey, ApiRequest, TagBuffer};
-use crate::proto::{Wired, WireRead, WireWrite};
+use crate::proto::{Wired, WireRead, Error};
+use crate::KafkaCode;
+use byteorder::{BigEndian, ByteOrder};
 
+// Generated from schemas/ApiVersionsRequest.json and schemas/ApiVersionsResponse.json
+kafka_schema!("ApiVersions", {
+    /// The response header has no tagged fields even in flexible versions, since clients
+    /// must be able to read it before knowing which versions the broker supports.
+    fn response_header_version(_version: usize) -> usize {
+        0
+    }
 
-impl ApiRequest for Request {
-    const API_KEY: ApiKey = ApiKey::ApiVersions;
-    const FLEXIBLE_VER: usize = 3;
-    type Response = ApiVersionsResponse;
+    /// Brokers answer versions they do not support with a v0 response carrying `UnsupportedVersion`.
+    fn decode_response(wire: &mut WireRead) -> Result<Response, Error> {
+        let unsupported = wire.buffer.len() >= 2
+            && KafkaCode::from(BigEndian::read_i16(wire.buffer)) == KafkaCode::UnsupportedVersion;
+        if unsupported {
+            wire.version = 0;
+        }
+        if wire.version >= Self::FLEXIBLE_VER {
+            Response::from_wire_compact(wire)
+        } else {
+            Response::from_wire(wire)
+        }
+    }
+});
+
+#[cfg(test)]
+impl Response {
+    /// Response of a broker supporting `versions`.
+    pub(crate) fn new(versions: &[(crate::proto::ApiKey, i16, i16)]) -> Self {
+        Self {
+            api_keys: versions.iter().map(|&(api_key, min_version, max_version)| ApiVersionsResponseKey {
+                api_key: api_key.into(),
+                min_version,
+                max_version,
+                ..Default::default()
+            }).collect(),
+            throttle_time_ms: Some(0),
+            ..Default::default()
+        }
+    }
 }
 
-#[derive(Debug, Wired, Default)]
-pub struct Request {
-    #[wired(since = 3)]
-    pub client_software_name: Option<String>,
-    #[wired(since = 3)]
-    pub client_software_version: Option<String>,
+#[test]
+fn test_tagged_fields() {
+    use bytes::{Bytes, BytesMut};
+    use crate::proto::WireWrite;
+
+    let data: &[u8] = &[
+        0, 0, // error_code
+        1, // api_keys: empty
+        0, 0, 0, 0, // throttle_time_ms
+        2, // tag count
+        1, 8, 0, 0, 0, 0, 0, 0, 0, 5, // finalized_features_epoch: 5
+        7, 2, 0xab, 0xcd, // unknown tag 7
+    ];
+    let mut buf = Bytes::from_static(data);
+    let res = Response::from_wire_compact(&mut WireRead { version: 3, buffer: &mut buf }).unwrap();
+    assert!(buf.is_empty());
+    assert_eq!(res.finalized_features_epoch, Some(5));
+    assert!(res.supported_features.is_none());
+    assert_eq!(res.tags.as_ref().unwrap().fields, vec![(7, Bytes::from_static(&[0xab, 0xcd]))]);
 
-    #[wired(since = 3)]
-    pub tags: Option<TagBuffer>,
+    let mut out = BytesMut::new();
+    res.to_wire_compact(&mut WireWrite { version: 3, buffer: &mut out });
+    assert_eq!(&out[..], data);
 }
 
-#[derive(Wired, Debug)]
-pub struct ApiVersionsItem {
-    pub api_key: ApiKey,
-    pub min_version: i16,
-    pub max_version: i16,
 
-    #[wired(since = 3)]
-    tags: Option<TagBuffer>,
-}
+#[test]
+fn test_unsupported_version_fallback() {
+    use bytes::Bytes;
+    use crate::proto::{ApiKey, ApiRequest};
 
-#[derive(Wired, Debug)]
-pub struct ApiVersionsResponse {
-    pub error_code: i16,
-    pub versions: Vec<ApiVersionsItem>,
-    #[wired(since = 1)]
-    pub throttle_time_ms: Option<i32>,
+    // v0 response sent by a broker which does not support the requested version
+    let data: &[u8] = &[
+        0, 35, // error_code: UnsupportedVersion
+        0, 0, 0, 1, // api_keys
+        0, 18, 0, 0, 0, 2, // ApiVersions 0..=2
+    ];
+    let mut buf = Bytes::from_static(data);
+    let res = Request::decode_response(&mut WireRead { version: 3, buffer: &mut buf }).unwrap();
+    assert!(buf.is_empty());
+    assert_eq!(res.error_code, 35);
+    assert_eq!(ApiKey::from(res.api_keys[0].api_key), ApiKey::ApiVersions);
+    assert_eq!(res.api_keys[0].max_version, 2);
 
-    #[wired(since = 3)]
-    tags: Option<TagBuffer>,
+    assert_eq!(Request::response_header_version(3), 0);
+    assert_eq!(Request::request_header_version(3), 2);
 }
-
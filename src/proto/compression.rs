@@ -0,0 +1,193 @@
+use std::io::{self, Read, Write};
+
+/// Limit of the decompressed size of a batch, so small batches can't expand into unbounded memory.
+pub const MAX_DECOMPRESSED_SIZE: usize = 256 * 1024 * 1024;
+
+#[cfg(any(feature = "gzip", feature = "snappy", feature = "lz4", feature = "zstd"))]
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Decompressed data exceeds {} bytes", MAX_DECOMPRESSED_SIZE))
+}
+
+/// Reads `reader` to the end, failing once more than [MAX_DECOMPRESSED_SIZE] bytes were read.
+#[cfg(any(feature = "gzip", feature = "lz4", feature = "zstd"))]
+fn read_limited(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    reader.take(MAX_DECOMPRESSED_SIZE as u64 + 1).read_to_end(&mut out)?;
+    if out.len() > MAX_DECOMPRESSED_SIZE {
+        return Err(too_large());
+    }
+    Ok(out)
+}
+
+/// Compression codec of a record batch, stored in the lowest 3 bits of the batch attributes.
+///
+/// Codecs other than `None` are only available with the cargo feature of the same name.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "snappy")]
+    Snappy,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    pub const ATTR_MASK: i16 = 0x07;
+
+    /// Codec stored in the batch `attrs`, or the raw codec id if it is unknown or not compiled in.
+    pub fn from_attrs(attrs: i16) -> Result<Self, u8> {
+        match attrs & Self::ATTR_MASK {
+            0 => Ok(Compression::None),
+            #[cfg(feature = "gzip")]
+            1 => Ok(Compression::Gzip),
+            #[cfg(feature = "snappy")]
+            2 => Ok(Compression::Snappy),
+            #[cfg(feature = "lz4")]
+            3 => Ok(Compression::Lz4),
+            #[cfg(feature = "zstd")]
+            4 => Ok(Compression::Zstd),
+            other => Err(other as u8),
+        }
+    }
+
+    pub fn id(self) -> i16 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => 1,
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => 2,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => 3,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => 4,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut enc = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                enc.write_all(data).expect("In-memory gzip");
+                enc.finish().expect("In-memory gzip")
+            }
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => {
+                snap::raw::Encoder::new().compress_vec(data).expect("Snappy input size")
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                // Kafka only supports frames with independent blocks
+                let info = lz4_flex::frame::FrameInfo::new()
+                    .block_mode(lz4_flex::frame::BlockMode::Independent);
+                let mut enc = lz4_flex::frame::FrameEncoder::with_frame_info(info, vec![]);
+                enc.write_all(data).expect("In-memory lz4");
+                enc.finish().expect("In-memory lz4")
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                zstd::stream::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL).expect("In-memory zstd")
+            }
+        }
+    }
+
+    /// Decompresses `data`, failing when it expands beyond [MAX_DECOMPRESSED_SIZE].
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => read_limited(flate2::read::GzDecoder::new(data)),
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => snappy_decompress(data),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => read_limited(lz4_flex::frame::FrameDecoder::new(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => read_limited(zstd::stream::read::Decoder::new(data)?),
+        }
+    }
+}
+
+#[cfg(feature = "snappy")]
+const XERIAL_MAGIC: &[u8] = b"\x82SNAPPY\x00";
+
+/// The java client writes snappy data in the xerial framing, other clients use raw snappy blocks.
+#[cfg(feature = "snappy")]
+fn snappy_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    use byteorder::{BigEndian, ByteOrder};
+    use io::{Error, ErrorKind};
+    use std::convert::TryFrom;
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid xerial snappy framing");
+
+    // Blocks declare their decompressed length, checked before allocating
+    let mut out = vec![];
+    let mut decompress_block = |block: &[u8]| -> io::Result<()> {
+        let len = snap::raw::decompress_len(block).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if out.len().saturating_add(len) > MAX_DECOMPRESSED_SIZE {
+            return Err(too_large());
+        }
+        let start = out.len();
+        out.resize(start + len, 0);
+        snap::raw::Decoder::new().decompress(block, &mut out[start..])
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(())
+    };
+    if !data.starts_with(XERIAL_MAGIC) {
+        decompress_block(data)?;
+        return Ok(out);
+    }
+
+    // Magic is followed by i32 version and i32 compatible version
+    let mut data = data.get(XERIAL_MAGIC.len() + 8..).ok_or_else(invalid)?;
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return Err(invalid());
+        }
+        let len = usize::try_from(BigEndian::read_u32(data)).map_err(|_| invalid())?;
+        let end = len.checked_add(4).ok_or_else(invalid)?;
+        decompress_block(data.get(4..end).ok_or_else(invalid)?)?;
+        data = &data[end..];
+    }
+    Ok(out)
+}
+
+#[cfg(all(feature = "gzip", feature = "snappy", feature = "lz4", feature = "zstd"))]
+#[test]
+fn test_codec_roundtrip() {
+    let data = b"hello hello hello hello hello hello".repeat(100);
+    for codec in &[Compression::None, Compression::Gzip, Compression::Snappy, Compression::Lz4, Compression::Zstd] {
+        let compressed = codec.compress(&data);
+        assert_eq!(Compression::from_attrs(codec.id()), Ok(*codec));
+        assert_eq!(codec.decompress(&compressed).unwrap(), data);
+    }
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy_xerial() {
+    let data = b"xerial framed snappy".to_vec();
+    let block = snap::raw::Encoder::new().compress_vec(&data).unwrap();
+
+    let mut framed = XERIAL_MAGIC.to_vec();
+    framed.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1]);
+    for _ in 0..2 {
+        framed.extend_from_slice(&(block.len() as i32).to_be_bytes());
+        framed.extend_from_slice(&block);
+    }
+
+    assert_eq!(Compression::Snappy.decompress(&framed).unwrap(), data.repeat(2));
+
+    // Block lengths with the sign bit set, and blocks declaring a huge decompressed length
+    let mut framed = XERIAL_MAGIC.to_vec();
+    framed.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff, 0]);
+    assert!(Compression::Snappy.decompress(&framed).is_err());
+    let bomb = [0x80, 0x80, 0x80, 0x80, 0x02, 0];
+    let err = Compression::Snappy.decompress(&bomb).unwrap_err();
+    assert_eq!(err.to_string(), format!("Decompressed data exceeds {} bytes", MAX_DECOMPRESSED_SIZE));
+}
@@ -1,46 +1,42 @@
-use crate::proto::{TagBuffer, ApiRequest, ApiKey};
-
-impl ApiRequest for Request {
-    const API_KEY: ApiKey = ApiKey::CreatePartitions;
-    const FLEXIBLE_VER: usize = 2;
-    type Response = Response;
-}
-
-#[derive(Debug, Wired)]
-pub struct TopicItem {
-    name: String,
-    count: i32,
-    assignments: Vec<i32>,
-
-    #[wired(since = 6)]
-    tags: Option<TagBuffer>,
-}
-
-#[derive(Debug, Wired)]
-pub struct Request {
-    topics: Vec<TopicItem>,
-    timeout_ms: i32,
-    validate_only: bool,
-
-    #[wired(since = 6)]
-    tags: Option<TagBuffer>,
-}
-
-#[derive(Debug, Wired)]
-pub struct ResItem {
-    name: String,
-    error_code: i16,
-    error_message: Option<String>,
-
-    #[wired(since = 2)]
-    tags: Option<TagBuffer>,
+// Generated from schemas/CreatePartitionsRequest.json and schemas/CreatePartitionsResponse.json
+kafka_schema!("CreatePartitions");
+
+#[test]
+fn test_generated_request() {
+    use crate::proto::{ApiRequest, Wired, WireWrite, WireRead};
+    use bytes::BytesMut;
+
+    assert_eq!(Request::FLEXIBLE_VER, 2);
+    assert_eq!(Request::VERSIONS, (0, 3));
+
+    let req = Request {
+        topics: vec![CreatePartitionsTopic {
+            name: "t".to_string(),
+            count: 2,
+            assignments: None,
+            tags: Some(Default::default()),
+        }],
+        timeout_ms: 100,
+        validate_only: false,
+        tags: Some(Default::default()),
+    };
+
+    let mut buf = BytesMut::new();
+    req.to_wire_compact(&mut WireWrite { version: 2, buffer: &mut buf });
+    assert_eq!(&buf[..], &[
+        2, // topics
+        2, b't', 0, 0, 0, 2, 0, // name, count, null assignments
+        0, // topic tags
+        0, 0, 0, 100, 0,
+        0, // tags
+    ][..]);
+
+    let mut buf = BytesMut::new();
+    req.to_wire(&mut WireWrite { version: 1, buffer: &mut buf });
+    assert_eq!(&buf[4..12], &[0, 1, b't', 0, 0, 0, 2, 0xff][..]);
+
+    let mut data = buf.freeze();
+    let decoded = Request::from_wire(&mut WireRead { version: 1, buffer: &mut data }).unwrap();
+    assert_eq!(decoded.topics[0].name, "t");
+    assert!(decoded.tags.is_none());
 }
-
-#[derive(Debug, Wired)]
-pub struct Response {
-    throttle_time_ms: i32,
-    results: Vec<ResItem>,
-
-    #[wired(since = 2)]
-    tags: Option<TagBuffer>,
-}
\ No newline at end of file
@@ -1,73 +1,77 @@
-use crate::proto::{Wired, WireRead, WireWrite, IsolationLevel, TopicMap, RecordBatch, ApiKey, ApiRequest};
+use crate::proto::{Batch, FetchedRecord, CorruptMessage, ErrorCode};
+use crate::client::Client;
+use tokio::io::{AsyncRead, AsyncWrite};
+use std::future::Future;
 
-// Fetch does not use compact encoding
-impl ApiRequest for Request {
-    const API_KEY: ApiKey = ApiKey::Fetch;
-    const FLEXIBLE_VER: usize = 99;
+// Generated from schemas/FetchRequest.json and schemas/FetchResponse.json
+kafka_schema!("Fetch");
 
-    type Response = Response;
+impl ErrorCode for PartitionData {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
 }
 
-#[derive(Wired)]
-pub struct FetchPartitions {
-    partition: i32,
-    #[wired(since = 9)]
-    current_leader_epoch: Option<i32>,
-    offset: i64,
-    #[wired(since = 5)]
-    log_start_offset: Option<i64>,
-    max_bytes: i32,
-}
+impl PartitionData {
+    /// Fetched batches of this partition, followed by an error if decoding stopped at a corrupt batch.
+    pub fn batches<'a>(&'a self, topic: &'a str) -> impl Iterator<Item=Result<&'a Batch, CorruptMessage>> + 'a {
+        let corrupt = self.records.corrupt.as_ref().map(|corrupt| CorruptMessage {
+            topic: topic.to_string(),
+            partition: self.partition_index,
+            base_offset: corrupt.base_offset,
+            reason: corrupt.reason.clone(),
+        });
+        self.records.batches.iter().map(Ok).chain(corrupt.map(Err))
+    }
 
-#[derive(Wired)]
-pub struct Request {
-    replica_id: i32,
-    max_wait_ms: i32,
-    min_bytes: i32,
-    #[wired(since = 3)]
-    max_bytes: Option<i32>,
-    #[wired(since = 4)]
-    isolation: Option<IsolationLevel>,
-    #[wired(since = 7)]
-    session_id: Option<i32>,
-    #[wired(since = 7)]
-    session_epoch: Option<i32>,
-    topics: TopicMap<FetchPartitions>,
-    #[wired(since = 7)]
-    forgotten_topics_data: Option<TopicMap<i32>>,
-    #[wired(since = 11)]
-    rack_id: Option<String>,
+    /// Fetched records of this partition across all record formats, followed by an error if decoding stopped at a corrupt batch.
+    pub fn records<'a>(&'a self, topic: &'a str) -> impl Iterator<Item=Result<FetchedRecord, CorruptMessage>> + 'a {
+        self.batches(topic).flat_map(|batch| -> Box<dyn Iterator<Item=_>> {
+            match batch {
+                Ok(batch) => Box::new(batch.records().map(Ok)),
+                Err(corrupt) => Box::new(std::iter::once(Err(corrupt))),
+            }
+        })
+    }
 }
 
-#[derive(Wired)]
-pub struct FetchResponseAbortedTx {
-    producer_id: i64,
-    first_offset: i64,
+impl Response {
+    pub fn partitions(&self) -> impl Iterator<Item=(&str, &PartitionData)> {
+        self.responses.iter().flat_map(|t| {
+            t.partitions.iter().map(move |p| (t.topic.as_str(), p))
+        })
+    }
 }
 
-#[derive(Wired)]
-pub struct FetchResponsePart {
-    partition: i32,
-    error_code: i16,
-    hwm: i64,
-    #[wired(since = 4)]
-    last_stable_offset: Option<i64>,
-    #[wired(since = 5)]
-    log_start_offset: Option<i64>,
-    #[wired(since = 4)]
-    aborted_transactions: Option<Vec<FetchResponseAbortedTx>>,
-    #[wired(since = 11)]
-    preferred_read_replica: Option<i32>,
-    record_set: RecordBatch,
+impl<S> Client<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    /// Fetches records, from the closest replica when [Config::client_rack](crate::config::Config::client_rack) is set
+    /// and the request does not name a rack itself.
+    pub fn fetch(&self, mut req: Request) -> impl Future<Output=crate::Result<Response>> {
+        if let (Some(rack), Some(rack_id)) = (&self.rack, &mut req.rack_id) {
+            if rack_id.is_empty() {
+                *rack_id = rack.clone();
+            }
+        }
+        self.call(req)
+    }
 }
 
-#[derive(Wired)]
-pub struct Response {
-    #[wired(since = 1)]
-    throttle_time_ms: Option<i32>,
-    #[wired(since = 7)]
-    error_code: Option<i16>,
-    #[wired(since = 7)]
-    session_id: Option<i32>,
-    responses: TopicMap<FetchResponsePart>,
+#[tokio::test]
+async fn test_fetch_rack() {
+    use crate::proto::ApiKey;
+    use crate::transport::mock;
+
+    let io = mock::spawn(|req| match req.header.api_key {
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3), (ApiKey::Fetch, 0, 11)])),
+        ApiKey::Fetch => {
+            assert_eq!(req.decode::<Request>().rack_id.as_deref(), Some("rack-1"));
+            Some(req.respond::<Request>(&Response::default()))
+        }
+        _ => None,
+    });
+    let config = crate::config::Config { client_rack: Some("rack-1".to_string()), ..Default::default() };
+    let client = Client::from_stream(io, &config).await.unwrap();
+    client.fetch(Request::default()).await.unwrap();
 }
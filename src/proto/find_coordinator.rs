@@ -1,31 +1,14 @@
-use crate::proto::{TagBuffer, ApiRequest, ApiKey};
+use crate::proto::ErrorCode;
 
-impl ApiRequest for Request {
-    const API_KEY: ApiKey = ApiKey::FindCoordinator;
-    const FLEXIBLE_VER: usize = 3;
-    type Response = Response;
-}
-#[derive(Debug, Wired)]
-pub struct Request {
-    key: String,
-    #[wired(since = 1)]
-    key_type: Option<i8>,
-
-    #[wired(since = 3)]
-    tags: Option<TagBuffer>,
-}
+// Generated from schemas/FindCoordinatorRequest.json and schemas/FindCoordinatorResponse.json
+kafka_schema!("FindCoordinator");
 
-#[derive(Debug, Wired)]
-pub struct Response {
-    #[wired(since = 1)]
-    throttle_time_ms: Option<i32>,
-    error_code: i16,
-    #[wired(since = 1)]
-    error_message: Option<Option<String>>,
-    node_id: i32,
-    host: String,
-    port: i32,
+impl ErrorCode for Response {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
 
-    #[wired(since = 3)]
-    tags: Option<TagBuffer>,
-}
\ No newline at end of file
+    fn error_message(&self) -> Option<&str> {
+        self.error_message.as_ref()?.as_deref()
+    }
+}
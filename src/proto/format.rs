@@ -2,14 +2,69 @@ use std::fmt::Display;
 use bytes::{BufMut, BytesMut, Buf, Bytes};
 use std::ops::{Deref, DerefMut};
 use byteorder::{ByteOrder, BigEndian};
-use crate::proto::{vint, uvint};
+use crate::proto::{vint, uvint, Corruption};
+
+/// Reason for a failed decode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// The buffer ended before the value could be read.
+    Truncated { expected: usize, remaining: usize },
+    /// A length prefix which is negative, or null for a non-nullable value.
+    InvalidLength(i64),
+    InvalidUtf8,
+    /// A varint which does not terminate within the maximum size of its type.
+    InvalidVarint,
+    /// A record batch which failed validation.
+    Corrupt(Corruption),
+}
 
-#[derive(Debug)]
-pub struct Error {}
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Truncated { expected, remaining } => {
+                write!(f, "expected {} bytes, but only {} remain", expected, remaining)
+            }
+            ErrorKind::InvalidLength(len) => write!(f, "invalid length {}", len),
+            ErrorKind::InvalidUtf8 => write!(f, "string is not valid utf-8"),
+            ErrorKind::InvalidVarint => write!(f, "varint is too long"),
+            ErrorKind::Corrupt(reason) => write!(f, "corrupt record batch, {}", reason),
+        }
+    }
+}
+
+/// Error returned when a message can not be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Fields which were being decoded, outermost first.
+    pub path: Vec<&'static str>,
+    /// Version of the message which was being decoded.
+    pub version: usize,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, version: usize) -> Self {
+        Self {
+            kind,
+            path: vec![],
+            version,
+        }
+    }
+
+    /// Prepends the field name to the path, called on the way out of nested decoders.
+    pub fn in_field(mut self, field: &'static str) -> Self {
+        self.path.insert(0, field);
+        self
+    }
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        unimplemented!()
+        if self.path.is_empty() {
+            write!(f, "Decoding version {} failed: {}", self.version, self.kind)
+        } else {
+            write!(f, "Decoding `{}` of version {} failed: {}", self.path.join("."), self.version, self.kind)
+        }
     }
 }
 
@@ -25,6 +80,55 @@ pub struct WireRead<'a> {
     pub(crate) buffer: &'a mut bytes::Bytes,
 }
 
+impl WireRead<'_> {
+    pub(crate) fn error(&self, kind: ErrorKind) -> Error {
+        Error::new(kind, self.version)
+    }
+
+    /// Fails unless at least `len` bytes remain in the buffer.
+    pub(crate) fn ensure(&self, len: usize) -> Result<(), Error> {
+        let remaining = self.buffer.remaining();
+        if remaining < len {
+            return Err(self.error(ErrorKind::Truncated { expected: len, remaining }));
+        }
+        Ok(())
+    }
+
+    /// Splits off the next `len` bytes.
+    pub(crate) fn take(&mut self, len: usize) -> Result<Bytes, Error> {
+        self.ensure(len)?;
+        Ok(self.buffer.split_to(len))
+    }
+
+    /// Reads an `i16`/`i32` length prefix, where negative values other than -1 are invalid.
+    fn length(&mut self, len: i64) -> Result<Option<usize>, Error> {
+        match len {
+            -1 => Ok(None),
+            len if len < 0 => Err(self.error(ErrorKind::InvalidLength(len))),
+            len => Ok(Some(len as usize)),
+        }
+    }
+
+    /// Reads a compact length prefix, which is stored incremented by one so 0 can encode null.
+    fn compact_length(&mut self) -> Result<Option<usize>, Error> {
+        let len: usize = uvint::from_wire(self)?.into();
+        Ok(len.checked_sub(1))
+    }
+
+    fn string(&self, data: Bytes) -> Result<String, Error> {
+        String::from_utf8(data.to_vec()).map_err(|_| self.error(ErrorKind::InvalidUtf8))
+    }
+
+    /// Reads `len` items, without trusting the length for the preallocation.
+    pub(crate) fn items<T>(&mut self, len: usize, read: impl Fn(&mut Self) -> Result<T, Error>) -> Result<Vec<T>, Error> {
+        let mut res = Vec::with_capacity(len.min(self.buffer.remaining()));
+        for _ in 0..len {
+            res.push(read(self)?);
+        }
+        Ok(res)
+    }
+}
+
 pub trait Wired: Sized {
     fn to_wire(&self, wire: &mut WireWrite);
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error>;
@@ -53,13 +157,11 @@ impl<T: Wired> Wired for Vec<T> {
         }
     }
 
+    // Null arrays are decoded as empty, since some fields declared as non-nullable are sent as null by brokers.
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let len = wire.buffer.get_i32();
-        let mut res = Vec::with_capacity(len as _);
-        for i in 0..len {
-            res.push(T::from_wire(wire)?);
-        }
-        Ok(res)
+        let len = i32::from_wire(wire)?;
+        let len = wire.length(len as i64)?.unwrap_or(0);
+        wire.items(len, T::from_wire)
     }
 
     fn to_wire_compact(&self, wire: &mut WireWrite) {
@@ -70,13 +172,8 @@ impl<T: Wired> Wired for Vec<T> {
     }
 
     fn from_wire_compact(wire: &mut WireRead) -> Result<Self, Error> {
-        let len: usize = uvint::from_wire(wire)?.into();
-        let len = len - 1;
-        let mut res = Vec::with_capacity(len as _);
-        for item in 0..len {
-            res.push(T::from_wire_compact(wire)?);
-        }
-        Ok(res)
+        let len = wire.compact_length()?.unwrap_or(0);
+        wire.items(len, T::from_wire_compact)
     }
 }
 
@@ -87,9 +184,10 @@ impl Wired for String {
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let len = wire.buffer.get_i16();
-        let data = wire.buffer.split_to(len as _);
-        Ok(String::from_utf8(data.bytes().to_vec()).unwrap())
+        let len = i16::from_wire(wire)?;
+        let len = wire.length(len as i64)?.ok_or_else(|| wire.error(ErrorKind::InvalidLength(-1)))?;
+        let data = wire.take(len)?;
+        wire.string(data)
     }
 
     fn to_wire_compact(&self, wire: &mut WireWrite) {
@@ -98,9 +196,9 @@ impl Wired for String {
     }
 
     fn from_wire_compact(wire: &mut WireRead) -> Result<Self, Error> {
-        let len: usize = uvint::from_wire(wire)?.into();
-        let data = wire.buffer.split_to(len - 1usize);
-        Ok(String::from_utf8(data.bytes().to_vec()).unwrap())
+        let len = wire.compact_length()?.ok_or_else(|| wire.error(ErrorKind::InvalidLength(-1)))?;
+        let data = wire.take(len)?;
+        wire.string(data)
     }
 }
 
@@ -108,7 +206,7 @@ impl Wired for Option<String> {
     fn to_wire(&self, wire: &mut WireWrite) {
         match self {
             None => {
-                (-1 as i16).to_wire(wire);
+                (-1i16).to_wire(wire);
             }
             Some(v) => {
                 v.to_wire(wire);
@@ -117,12 +215,14 @@ impl Wired for Option<String> {
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let len = wire.buffer.get_i16();
-        if len < 0 {
-            return Ok(None);
+        let len = i16::from_wire(wire)?;
+        match wire.length(len as i64)? {
+            None => Ok(None),
+            Some(len) => {
+                let data = wire.take(len)?;
+                wire.string(data).map(Some)
+            }
         }
-        let data = wire.buffer.split_to(len as _);
-        Ok(Some(String::from_utf8(data.to_vec()).unwrap()))
     }
 
     fn to_wire_compact(&self, wire: &mut WireWrite) {
@@ -137,12 +237,13 @@ impl Wired for Option<String> {
     }
 
     fn from_wire_compact(wire: &mut WireRead) -> Result<Self, Error> {
-        let len: usize = uvint::from_wire(wire)?.into();
-        if len == 0 {
-            return Ok(None);
+        match wire.compact_length()? {
+            None => Ok(None),
+            Some(len) => {
+                let data = wire.take(len)?;
+                wire.string(data).map(Some)
+            }
         }
-        let data = wire.buffer.split_to(len - 1);
-        Ok(Some(String::from_utf8(data.bytes().to_vec()).unwrap()))
     }
 }
 
@@ -153,9 +254,9 @@ impl Wired for Bytes {
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let len = wire.buffer.get_i32();
-        let data = wire.buffer.split_to(len as _);
-        Ok(data)
+        let len = i32::from_wire(wire)?;
+        let len = wire.length(len as i64)?.ok_or_else(|| wire.error(ErrorKind::InvalidLength(-1)))?;
+        wire.take(len)
     }
 
     fn to_wire_compact(&self, wire: &mut WireWrite) {
@@ -164,14 +265,73 @@ impl Wired for Bytes {
     }
 
     fn from_wire_compact(wire: &mut WireRead) -> Result<Self, Error> {
-        let len: usize = uvint::from_wire(wire)?.into();
+        let len = wire.compact_length()?.ok_or_else(|| wire.error(ErrorKind::InvalidLength(-1)))?;
+        wire.take(len)
+    }
+}
+
+impl Wired for Option<Bytes> {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        match self {
+            None => (-1i32).to_wire(wire),
+            Some(v) => v.to_wire(wire),
+        }
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        let len = i32::from_wire(wire)?;
+        match wire.length(len as i64)? {
+            None => Ok(None),
+            Some(len) => wire.take(len).map(Some),
+        }
+    }
+
+    fn to_wire_compact(&self, wire: &mut WireWrite) {
+        match self {
+            None => uvint::from(0).to_wire(wire),
+            Some(v) => v.to_wire_compact(wire),
+        }
+    }
+
+    fn from_wire_compact(wire: &mut WireRead) -> Result<Self, Error> {
+        match wire.compact_length()? {
+            None => Ok(None),
+            Some(len) => wire.take(len).map(Some),
+        }
+    }
+}
+
+impl<T: Wired> Wired for Option<Vec<T>> {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        match self {
+            None => (-1i32).to_wire(wire),
+            Some(v) => v.to_wire(wire),
+        }
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        let len = i32::from_wire(wire)?;
+        match wire.length(len as i64)? {
+            None => Ok(None),
+            Some(len) => wire.items(len, T::from_wire).map(Some),
+        }
+    }
+
+    fn to_wire_compact(&self, wire: &mut WireWrite) {
+        match self {
+            None => uvint::from(0).to_wire(wire),
+            Some(v) => v.to_wire_compact(wire),
+        }
+    }
 
-        let data = wire.buffer.split_to(len - 1usize);
-        Ok(data)
+    fn from_wire_compact(wire: &mut WireRead) -> Result<Self, Error> {
+        match wire.compact_length()? {
+            None => Ok(None),
+            Some(len) => wire.items(len, T::from_wire_compact).map(Some),
+        }
     }
 }
 
-// TODO: Option<bytes> for NULLABLE_BYTES
 impl Wired for bool {
     #[inline(always)]
     fn to_wire(&self, wire: &mut WireWrite) {
@@ -179,7 +339,7 @@ impl Wired for bool {
     }
     #[inline(always)]
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        Ok(wire.buffer.get_i8() != 0)
+        Ok(i8::from_wire(wire)? != 0)
     }
 }
 
@@ -189,16 +349,29 @@ impl Wired for i8 {
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(1)?;
         Ok(wire.buffer.get_i8())
     }
 }
 
+impl Wired for u16 {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        wire.buffer.put_u16(*self);
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(2)?;
+        Ok(wire.buffer.get_u16())
+    }
+}
+
 impl Wired for i16 {
     fn to_wire(&self, wire: &mut WireWrite) {
         wire.buffer.put_i16(*self);
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(2)?;
         Ok(wire.buffer.get_i16())
     }
 }
@@ -209,17 +382,41 @@ impl Wired for i32 {
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(4)?;
         Ok(wire.buffer.get_i32())
     }
 }
 
+impl Wired for u32 {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        wire.buffer.put_u32(*self);
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(4)?;
+        Ok(wire.buffer.get_u32())
+    }
+}
+
 impl Wired for i64 {
     fn to_wire(&self, wire: &mut WireWrite) {
         wire.buffer.put_i64(*self);
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(8)?;
         Ok(wire.buffer.get_i64())
     }
 }
 
+
+impl Wired for f64 {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        wire.buffer.put_f64(*self);
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        wire.ensure(8)?;
+        Ok(wire.buffer.get_f64())
+    }
+}
@@ -0,0 +1,213 @@
+//! Legacy message sets, the record format with magic 0 and 1 which preceded [RecordBatch](crate::proto::RecordBatch).
+//!
+//! https://kafka.apache.org/documentation/#messageset
+use bytes::{Buf, Bytes, BytesMut, BufMut};
+use byteorder::{BigEndian, ByteOrder};
+use crate::proto::{Wired, WireRead, WireWrite, Compression, Corruption, CorruptBatch, BATCH_LOG_OVERHEAD};
+
+/// Size of the smallest possible message, a magic 0 message with null key and value.
+const MIN_MESSAGE_SIZE: usize = 14;
+/// Attribute bit set when the broker overwrote the timestamps with its log append time.
+const TIMESTAMP_TYPE_MASK: i8 = 0x08;
+
+/// Single magic 0 or 1 message with its absolute offset.
+///
+/// Compressed wrapper messages are expanded into their inner messages while decoding, so
+/// `attrs` never carries a compression codec on decoded messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub offset: i64,
+    pub magic: i8,
+    pub attrs: i8,
+    /// Only present in magic 1 messages.
+    pub timestamp: Option<i64>,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+}
+
+impl Message {
+    /// Reads one top-level entry of a message set, expanding compressed wrapper messages.
+    pub fn read_entry(wire: &mut WireRead) -> Result<Vec<Message>, CorruptBatch> {
+        let message = Message::read_message(wire)?;
+        let codec = Compression::from_attrs(message.attrs as i16)
+            .map_err(|codec| CorruptBatch::new(message.offset, Corruption::UnsupportedCompression(codec)))?;
+        if codec == Compression::None {
+            return Ok(vec![message]);
+        }
+        let offset = message.offset;
+        message.expand(codec).map_err(|reason| CorruptBatch::new(offset, reason))
+    }
+
+    /// Reads a single message, without expanding it.
+    fn read_message(wire: &mut WireRead) -> Result<Message, CorruptBatch> {
+        let truncated = || CorruptBatch::new(-1, Corruption::Truncated);
+        if wire.buffer.len() < BATCH_LOG_OVERHEAD {
+            return Err(truncated());
+        }
+        let offset = i64::from_wire(wire).map_err(|_| truncated())?;
+        let corrupt = |reason| CorruptBatch::new(offset, reason);
+
+        let size = i32::from_wire(wire).map_err(|_| truncated())?;
+        if size < MIN_MESSAGE_SIZE as i32 {
+            return Err(corrupt(Corruption::InvalidLength(size)));
+        }
+        if size as usize > wire.buffer.len() {
+            return Err(corrupt(Corruption::Truncated));
+        }
+        let mut body = wire.buffer.split_to(size as usize);
+
+        let expected = BigEndian::read_u32(&body[0..]);
+        let computed = crc32fast::hash(&body[4..]);
+        if expected != computed {
+            return Err(corrupt(Corruption::CrcMismatch { expected, computed }));
+        }
+        let magic = body[4] as i8;
+        let attrs = body[5] as i8;
+        if !(0..=1).contains(&magic) {
+            return Err(corrupt(Corruption::InvalidMagic(magic)));
+        }
+        let min_size = if magic == 1 { MIN_MESSAGE_SIZE + 8 } else { MIN_MESSAGE_SIZE };
+        if body.len() < min_size {
+            return Err(corrupt(Corruption::InvalidLength(size)));
+        }
+        body.advance(6);
+
+        let mut wire = WireRead {
+            version: wire.version,
+            buffer: &mut body,
+        };
+        let mut read = || -> Result<Message, crate::proto::Error> {
+            Ok(Message {
+                offset,
+                magic,
+                attrs,
+                timestamp: if magic == 1 { Some(i64::from_wire(&mut wire)?) } else { None },
+                key: Wired::from_wire(&mut wire)?,
+                value: Wired::from_wire(&mut wire)?,
+            })
+        };
+        read().map_err(|_| corrupt(Corruption::InvalidRecords))
+    }
+
+    /// Decompresses the inner message set of a wrapper message, which may only contain uncompressed messages.
+    fn expand(self, codec: Compression) -> Result<Vec<Message>, Corruption> {
+        let value = self.value.unwrap_or_default();
+        let mut data: Bytes = codec.decompress(&value).map_err(|_| Corruption::Decompression)?.into();
+
+        let mut inner = vec![];
+        while !data.is_empty() {
+            let mut wire = WireRead {
+                version: 0,
+                buffer: &mut data,
+            };
+            let message = Message::read_message(&mut wire).map_err(|c| c.reason)?;
+            if message.attrs & Compression::ATTR_MASK as i8 != 0 {
+                return Err(Corruption::NestedCompression);
+            }
+            inner.push(message);
+        }
+
+        // Inner messages of magic 1 wrappers carry offsets relative to the first one,
+        // and the wrapper carries the absolute offset of the last inner message.
+        if self.magic == 1 {
+            let last = inner.last().map(|m| m.offset).unwrap_or(0);
+            for message in inner.iter_mut() {
+                message.offset += self.offset - last;
+                if self.attrs & TIMESTAMP_TYPE_MASK != 0 {
+                    message.timestamp = self.timestamp;
+                }
+            }
+        }
+        Ok(inner)
+    }
+
+    /// Writes the message as an uncompressed message set entry.
+    pub fn write_entry(&self, wire: &mut WireWrite) {
+        let mut body = BytesMut::new();
+        let mut inner = WireWrite {
+            version: wire.version,
+            buffer: &mut body,
+        };
+        self.magic.to_wire(&mut inner);
+        (self.attrs & !(Compression::ATTR_MASK as i8)).to_wire(&mut inner);
+        if self.magic == 1 {
+            self.timestamp.unwrap_or(-1).to_wire(&mut inner);
+        }
+        self.key.to_wire(&mut inner);
+        self.value.to_wire(&mut inner);
+
+        self.offset.to_wire(wire);
+        (body.len() as i32 + 4).to_wire(wire);
+        crc32fast::hash(&body).to_wire(wire);
+        wire.buffer.put(body);
+    }
+}
+
+#[test]
+fn test_message_roundtrip() {
+    let messages = vec![
+        Message { offset: 3, magic: 0, attrs: 0, timestamp: None, key: None, value: Some(Bytes::from_static(b"v0")) },
+        Message { offset: 4, magic: 1, attrs: 0, timestamp: Some(1000), key: Some(Bytes::from_static(b"k")), value: None },
+    ];
+
+    let mut buf = BytesMut::new();
+    for message in &messages {
+        message.write_entry(&mut WireWrite { version: 0, buffer: &mut buf });
+    }
+    // offset, size, crc, magic, attrs, key, value
+    assert_eq!(BigEndian::read_i32(&buf[8..]), 4 + 1 + 1 + 4 + 4 + 2);
+
+    let mut data = buf.freeze();
+    let mut decoded = vec![];
+    while !data.is_empty() {
+        decoded.extend(Message::read_entry(&mut WireRead { version: 0, buffer: &mut data }).unwrap());
+    }
+    assert_eq!(decoded, messages);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_compressed_wrapper() {
+    let inner = (0..3).map(|i| Message {
+        offset: i,
+        magic: 1,
+        attrs: 0,
+        timestamp: Some(1000 + i),
+        key: None,
+        value: Some(Bytes::from_static(b"inner")),
+    }).collect::<Vec<_>>();
+
+    let mut set = BytesMut::new();
+    for message in &inner {
+        message.write_entry(&mut WireWrite { version: 0, buffer: &mut set });
+    }
+
+    // Wrapper messages are written by hand, since `write_entry` does not compress
+    let wrap = |offset: i64, set: &[u8]| {
+        let mut body = BytesMut::new();
+        body.put_i8(1);
+        body.put_i8(Compression::Gzip.id() as i8);
+        body.put_i64(1002);
+        body.put_i32(-1);
+        let compressed = Compression::Gzip.compress(set);
+        body.put_i32(compressed.len() as i32);
+        body.put_slice(&compressed);
+
+        let mut buf = BytesMut::new();
+        buf.put_i64(offset);
+        buf.put_i32(body.len() as i32 + 4);
+        buf.put_u32(crc32fast::hash(&body));
+        buf.put(body);
+        buf
+    };
+
+    let mut data = wrap(42, &set).freeze();
+    let decoded = Message::read_entry(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded.iter().map(|m| m.offset).collect::<Vec<_>>(), vec![40, 41, 42]);
+    assert_eq!(decoded[1].timestamp, Some(1001));
+
+    // Wrappers are rejected instead of recursing into them
+    let mut data = wrap(43, &wrap(42, &set)).freeze();
+    let err = Message::read_entry(&mut WireRead { version: 0, buffer: &mut data }).unwrap_err();
+    assert_eq!(err, CorruptBatch::new(43, Corruption::NestedCompression));
+}
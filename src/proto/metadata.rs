@@ -1,118 +1,63 @@
-use crate::proto::{Wired, WireRead, WireWrite, ApiRequest, ApiKey, TagBuffer};
+use crate::proto::{ApiKey, ErrorCode};
 use crate::client::Client;
+use tokio::io::{AsyncRead, AsyncWrite};
 use std::future::Future;
-use tower::{ServiceExt, Service};
-use std::ops::DerefMut;
-use crate::transport::CallReq;
 
-impl ApiRequest for Request {
-    const API_KEY: ApiKey = ApiKey::Metadata;
-    const FLEXIBLE_VER: usize = 9;
-    type Response = Response;
-}
-
-#[derive(Debug, Wired)]
-pub struct Topic {
-    value: String,
-
-    #[wired(since = 9)]
-    tags: Option<TagBuffer>,
-}
-
-#[derive(Debug, Wired)]
-pub struct Request {
-    topics: Vec<Topic>,
-    #[wired(since = 4)]
-    allow_auto_topic_creation: Option<bool>,
-    #[wired(since = 8)]
-    include_cluster_auth_ops: Option<bool>,
-    #[wired(since = 8)]
-    include_topic_auth_ops: Option<bool>,
-
-    #[wired(since = 9)]
-    tags: Option<TagBuffer>,
-}
-
-
-#[derive(Debug, Wired)]
-pub struct MetadataPartition {
-    error_code: i16,
-    part_index: i32,
-    leader_id: i32,
-    #[wired(since = 7)]
-    leader_epoch: Option<i32>,
-    replicas: Vec<i32>,
-    isr_nodes: Vec<i32>,
-    #[wired(since = 5)]
-    offline_replicas: Option<Vec<i32>>,
-
-    #[wired(since = 9)]
-    tags: Option<TagBuffer>,
-}
-
-
-#[derive(Debug, Wired)]
-pub struct MetadataBroker {
-    node_id: i32,
-    host: String,
-    port: i32,
-    #[wired(since = 1)]
-    rack: Option<Option<String>>,
-
-    #[wired(since = 9)]
-    tags: Option<TagBuffer>,
-}
-
-#[derive(Debug, Wired)]
-pub struct MetadataTopic {
-    error_code: i16,
-    name: String,
-
-    #[wired(since = 1)]
-    is_internal: Option<bool>,
-    parts: Vec<MetadataPartition>,
-
-    #[wired(since = 8)]
-    topic_auth_ops: Option<i32>,
+// Generated from schemas/MetadataRequest.json and schemas/MetadataResponse.json
+kafka_schema!("Metadata");
 
-    #[wired(since = 9)]
-    tags: Option<TagBuffer>,
+impl ErrorCode for MetadataResponsePartition {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
 }
 
-#[derive(Debug, Wired)]
-pub struct Response {
-    #[wired(since = 3)]
-    throttle_time_ms: Option<i32>,
-    brokers: Vec<MetadataBroker>,
-    #[wired(since = 2)]
-    cluster_id: Option<Option<String>>,
-    #[wired(since = 1)]
-    controller_id: Option<i32>,
-    topics: Vec<MetadataTopic>,
-    #[wired(since = 8)]
-    cluster_auth_ops: Option<i32>,
-
-    #[wired(since = 9)]
-    tags: Option<TagBuffer>,
+impl ErrorCode for MetadataResponseTopic {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
 }
 
-impl Client {
+impl<S> Client<S>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
     pub fn metadata(&self, topics: Vec<String>) -> impl Future<Output=crate::Result<Response>> {
         let req = Request {
-            topics: topics.into_iter().map(|t| Topic { value: t, tags: TagBuffer {}.into() }).collect(),
-            allow_auto_topic_creation: true.into(),
-            include_cluster_auth_ops: true.into(),
-            include_topic_auth_ops: true.into(),
-            tags: TagBuffer {}.into(),
+            topics: Some(topics.into_iter().map(|name| MetadataRequestTopic { name, ..Default::default() }).collect()),
+            include_cluster_authorized_operations: Some(true),
+            include_topic_authorized_operations: Some(true),
+            ..Default::default()
         };
-        let client = self.client.clone();
-        let versions = self.version_match(ApiKey::Metadata, (4, 99));
-        async move {
-            let mut client = client.lock().await;
-            let client = ServiceExt::<()>::ready_oneshot(client.deref_mut()).await?;
-            let res = client.call(CallReq::new(versions.1, req)).await?;
+        self.call(req)
+    }
+}
 
-            Ok(res)
+#[cfg(test)]
+impl Response {
+    /// Response listing `brokers` by node id and host, and the leader of each partition of `topics`.
+    pub(crate) fn new(brokers: &[(i32, &str)], topics: &[(&str, &[(i32, i32)])]) -> Self {
+        Response {
+            throttle_time_ms: Some(0),
+            brokers: brokers.iter().map(|&(node_id, host)| MetadataResponseBroker {
+                node_id,
+                host: host.to_string(),
+                port: 9092,
+                ..Default::default()
+            }).collect(),
+            controller_id: Some(brokers[0].0),
+            topics: topics.iter().map(|&(name, parts)| MetadataResponseTopic {
+                name: name.to_string(),
+                partitions: parts.iter().map(|&(partition_index, leader_id)| MetadataResponsePartition {
+                    partition_index,
+                    leader_id,
+                    leader_epoch: Some(0),
+                    replica_nodes: vec![leader_id],
+                    isr_nodes: vec![leader_id],
+                    ..Default::default()
+                }).collect(),
+                ..Default::default()
+            }).collect(),
+            ..Default::default()
         }
     }
 }
@@ -131,6 +76,81 @@ async fn test_metadata() {
 
     assert_eq!(meta.topics[0].name, "test");
     assert_eq!(meta.topics[0].error_code, 0);
-    assert_eq!(meta.topics[0].parts[0].error_code, 0);
+    assert_eq!(meta.topics[0].partitions[0].error_code, 0);
     dbg!(meta);
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_metadata_stream() {
+    use crate::transport::mock;
+
+    let io = mock::spawn(|req| match req.header.api_key {
+        ApiKey::ApiVersions => Some(mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3), (ApiKey::Metadata, 0, 9)])),
+        ApiKey::Metadata => {
+            let req_topics = req.decode::<Request>().topics;
+            Some(req.respond::<Request>(&Response {
+                throttle_time_ms: Some(0),
+                brokers: vec![MetadataResponseBroker {
+                    node_id: 1,
+                    host: "kafka".to_string(),
+                    port: 9092,
+                    ..Default::default()
+                }],
+                cluster_id: Some(Some("cluster".to_string())),
+                controller_id: Some(1),
+                topics: req_topics.unwrap_or_default().into_iter().map(|t| MetadataResponseTopic {
+                    name: t.name,
+                    ..Default::default()
+                }).collect(),
+                ..Default::default()
+            }))
+        }
+        _ => None,
+    });
+    let client = Client::from_stream(io, &Default::default()).await.unwrap();
+
+    let meta = client.metadata(vec!["test".to_string()]).await.unwrap();
+    assert_eq!(meta.brokers[0].host, "kafka");
+    assert_eq!(meta.cluster_id, Some(Some("cluster".to_string())));
+    assert_eq!(meta.topics[0].name, "test");
+}
+
+#[test]
+fn test_schema_defaults() {
+    use crate::proto::{Wired, WireRead, WireWrite};
+    use bytes::BytesMut;
+
+    // Nullable fields are only null when their default is null
+    let req = Request::default();
+    assert!(req.topics.unwrap().is_empty());
+    assert_eq!(req.allow_auto_topic_creation, Some(true));
+    let res = Response::default();
+    assert_eq!(res.controller_id, Some(-1));
+    assert_eq!(res.cluster_id, Some(None));
+    assert_eq!(res.cluster_authorized_operations, Some(i32::MIN));
+
+    // Versioned fields are set by default, so default messages can be encoded at any version
+    for version in 0..=9 {
+        let mut buf = BytesMut::new();
+        MetadataResponseBroker::default().to_wire(&mut WireWrite { version, buffer: &mut buf });
+        Response::default().to_wire(&mut WireWrite { version, buffer: &mut buf });
+    }
+
+    // Versioned fields left unset are encoded with their default instead of panicking
+    let encode = |req: &Request| {
+        let mut buf = BytesMut::new();
+        req.to_wire(&mut WireWrite { version: 8, buffer: &mut buf });
+        buf
+    };
+    let unset = Request {
+        allow_auto_topic_creation: None,
+        include_cluster_authorized_operations: None,
+        include_topic_authorized_operations: None,
+        ..Default::default()
+    };
+    assert_eq!(encode(&unset), encode(&Request::default()));
+    let mut buf = BytesMut::new();
+    Response { controller_id: None, cluster_id: None, ..Default::default() }.to_wire(&mut WireWrite { version: 8, buffer: &mut buf });
+    let res = Response::from_wire(&mut WireRead { version: 8, buffer: &mut buf.freeze() }).unwrap();
+    assert_eq!(res.controller_id, Some(-1));
+    assert_eq!(res.cluster_id, Some(None));
+}
@@ -1,4 +1,6 @@
 pub(crate) mod format;
+pub(crate) mod compression;
+pub(crate) mod message_set;
 pub(crate) mod fetch;
 pub(crate) mod produce;
 pub(crate) mod list_offsets;
@@ -16,21 +18,90 @@ pub(crate) mod describe_groups;
 pub(crate) mod list_groups;
 pub(crate) mod create_partitions;
 pub(crate) mod offset_delete;
+pub(crate) mod sasl_handshake;
+pub(crate) mod sasl_authenticate;
 
 use bytes::{BytesMut, Buf, Bytes, BufMut};
 
 pub use format::*;
+pub use compression::Compression;
+pub use message_set::Message;
 use std::ops::Shr;
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use crate::KafkaError;
 
 pub trait ApiRequest: Wired {
     const API_KEY: ApiKey;
     const FLEXIBLE_VER: usize;
+    /// Range of versions rafka can encode and decode, negotiated against the versions supported by the broker.
+    const VERSIONS: (usize, usize);
     type Response: Wired;
+
+    /// Request header version, v2 adds tagged fields to the header in flexible versions.
+    fn request_header_version(version: usize) -> usize {
+        if version >= Self::FLEXIBLE_VER { 2 } else { 1 }
+    }
+
+    /// Response header version, v1 adds tagged fields to the header in flexible versions.
+    fn response_header_version(version: usize) -> usize {
+        if version >= Self::FLEXIBLE_VER { 1 } else { 0 }
+    }
+
+    /// Decodes the response body, following the response header.
+    fn decode_response(wire: &mut WireRead) -> Result<Self::Response, Error> {
+        if wire.version >= Self::FLEXIBLE_VER {
+            Self::Response::from_wire_compact(wire)
+        } else {
+            Self::Response::from_wire(wire)
+        }
+    }
+
+    /// Time the client must hold back further requests to the broker, after a request exceeded a quota.
+    ///
+    /// Only counts in versions throttled by the client (KIP-219), brokers delay older responses themselves.
+    fn throttle_time(_version: usize, _res: &Self::Response) -> Option<Duration> {
+        None
+    }
+
+    /// Checks that the request can be encoded, called before it is sent.
+    fn validate(&self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// Highest version in both the `client` and the `broker` range, `None` when they don't overlap.
+pub fn negotiate_version(client: (usize, usize), broker: (usize, usize)) -> Option<usize> {
+    let (min, max) = (client.0.max(broker.0), client.1.min(broker.1));
+    if min <= max { Some(max) } else { None }
+}
+
+/// Throttle time of a response to an api throttled by the client since version `since`.
+pub(crate) fn client_throttle(version: usize, since: usize, throttle_time_ms: Option<i32>) -> Option<Duration> {
+    throttle_time_ms
+        .filter(|ms| version >= since && *ms > 0)
+        .map(|ms| Duration::from_millis(ms as u64))
 }
 
-#[derive(Clone, Copy, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+/// Response or part of a response which carries an error code.
+pub trait ErrorCode {
+    fn error_code(&self) -> i16;
+
+    /// Error message provided by the broker, only present in some responses.
+    fn error_message(&self) -> Option<&str> {
+        None
+    }
+
+    fn error(&self) -> Result<(), KafkaError> {
+        KafkaError::from_code(self.error_code(), self.error_message())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, Ord, PartialOrd, Eq, PartialEq, Wired)]
 #[repr(i16)]
 pub enum ApiKey {
+    #[default]
     Produce = 0,
     Fetch = 1,
     ListOffsets = 2,
@@ -43,14 +114,14 @@ pub enum ApiKey {
     OffsetFetch,
     FindCoordinator,
     JoinGroup,
-    Hearbeat,
+    Heartbeat,
     LeaveGroup,
     SyncGroup,
     DescribeGroups,
     ListGroups,
     SaslHandshake,
     ApiVersions,
-    CreateTopic,
+    CreateTopics,
     DeleteTopics,
     DeleteRecords,
     InitProducerId,
@@ -58,15 +129,15 @@ pub enum ApiKey {
     AddPartitionsToTxn,
     AddOffsetsToTxn,
     EndTxn,
-    WritneTxnMarkers,
+    WriteTxnMarkers,
     TxnOffsetCommit,
     DescribeAcls,
     CreateAcls,
-    DeleteAcs,
+    DeleteAcls,
     DescribeConfigs,
     AlterConfigs,
     AlterReplicaLogDirs,
-    DescriveLogDirs,
+    DescribeLogDirs,
     SaslAuthenticate,
     CreatePartitions,
     CreateDelegationToken,
@@ -81,54 +152,111 @@ pub enum ApiKey {
     OffsetDelete,
     DescribeClientQuotas,
     AlterClientQuotas = 49,
+    /// Api added in a broker version newer than this crate.
+    Unknown(i16),
 }
 
-impl Default for ApiKey {
-    fn default() -> Self {
-        return ApiKey::Produce;
-    }
-}
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub struct Uuid(pub [u8; 16]);
 
-impl Wired for ApiKey {
+impl Wired for Uuid {
     fn to_wire(&self, wire: &mut WireWrite) {
-        (unsafe { std::mem::transmute::<_, i16>(*self) }).to_wire(wire)
+        wire.buffer.put_slice(&self.0);
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let v = i16::from_wire(wire)?;
-        Ok(unsafe { std::mem::transmute::<_, Self>(v) })
+        let mut res = [0u8; 16];
+        wire.ensure(res.len())?;
+        wire.buffer.copy_to_slice(&mut res);
+        Ok(Self(res))
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Wired)]
 #[repr(i8)]
 pub enum IsolationLevel {
     ReadUncommited = 0,
     ReadCommited = 1,
+    Unknown(i8),
+}
+
+
+fn put_uvarint(wire: &mut WireWrite, mut val: u64) {
+    loop {
+        let mut c = (val & 0b01111111) as u8;
+        val >>= 7;
+        c |= ((val > 0) as u8 & 0b1) << 7;
+        wire.buffer.put_u8(c);
+        if val == 0 { break; }
+    }
+}
+
+fn get_uvarint(wire: &mut WireRead, max_bytes: usize) -> Result<u64, Error> {
+    let mut res: u64 = 0;
+    for i in 0..max_bytes {
+        wire.ensure(1)?;
+        let b = wire.buffer.get_u8();
+        res |= ((b & 0b01111111) as u64) << (i * 7);
+        if (b >> 7) == 0 {
+            return Ok(res);
+        }
+    }
+    Err(wire.error(ErrorKind::InvalidVarint))
+}
+
+/// Signed, zigzag-encoded variable length 32-bit integer used by the v2 record format.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct vint(pub i32);
+
+impl From<i32> for vint {
+    fn from(v: i32) -> Self {
+        Self(v)
+    }
+}
+
+impl From<vint> for i32 {
+    fn from(v: vint) -> Self {
+        v.0
+    }
 }
 
-impl Wired for IsolationLevel {
+impl Wired for vint {
     fn to_wire(&self, wire: &mut WireWrite) {
-        (unsafe { std::mem::transmute::<_, i8>(*self) }).to_wire(wire)
+        put_uvarint(wire, ((self.0 << 1) ^ (self.0 >> 31)) as u32 as u64)
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let v = i8::from_wire(wire)?;
-        Ok(unsafe { std::mem::transmute::<_, Self>(v) })
+        let v = get_uvarint(wire, 5)? as u32;
+        Ok(Self((v >> 1) as i32 ^ -((v & 1) as i32)))
     }
 }
 
-
+/// Signed, zigzag-encoded variable length 64-bit integer used by the v2 record format.
 #[allow(non_camel_case_types)]
-pub struct vint(isize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct vlong(pub i64);
 
-impl Wired for vint {
+impl From<i64> for vlong {
+    fn from(v: i64) -> Self {
+        Self(v)
+    }
+}
+
+impl From<vlong> for i64 {
+    fn from(v: vlong) -> Self {
+        v.0
+    }
+}
+
+impl Wired for vlong {
     fn to_wire(&self, wire: &mut WireWrite) {
-        unimplemented!()
+        put_uvarint(wire, ((self.0 << 1) ^ (self.0 >> 63)) as u64)
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        unimplemented!()
+        let v = get_uvarint(wire, 10)?;
+        Ok(Self((v >> 1) as i64 ^ -((v & 1) as i64)))
     }
 }
 
@@ -136,9 +264,9 @@ impl Wired for vint {
 #[allow(non_camel_case_types)]
 pub struct uvint(usize);
 
-impl Into<usize> for uvint {
-    fn into(self) -> usize {
-        self.0
+impl From<uvint> for usize {
+    fn from(v: uvint) -> Self {
+        v.0
     }
 }
 
@@ -150,88 +278,639 @@ impl From<usize> for uvint {
 
 impl Wired for uvint {
     fn to_wire(&self, wire: &mut WireWrite) {
-        let mut val = self.0;
-        loop {
-            let mut c = (val & 0b01111111) as u8;
-            val = val >> 7;
-            c |= ((val > 0) as u8 & 0b1) << 7;
-            println!("{:#b}", c);
-            wire.buffer.put_u8(c);
-            if val == 0 { break; }
-        }
+        put_uvarint(wire, self.0 as u64)
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let mut res: usize = 0;
-        let mut i = 0;
-        loop {
-            let b = wire.buffer.get_u8();
-            res |= ((b & 0b01111111) as usize) << (i * 7);
-            i += 1;
-            if (b >> 7) == 0 { break; }
-        }
-        return Ok(Self(res));
+        Ok(Self(get_uvarint(wire, 5)? as usize))
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TagBuffer {}
+/// Tagged fields of a flexible-version struct.
+///
+/// Holds the raw values of tags not declared on the struct, so they survive re-encoding.
+/// Fields declared with `#[wired(tag = N)]` are moved in and out of the buffer by the derive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagBuffer {
+    /// Raw tagged values, sorted by tag.
+    pub fields: Vec<(u32, Bytes)>,
+}
 
-impl Default for TagBuffer {
-    fn default() -> Self {
-        Self {}
+impl TagBuffer {
+    pub fn get(&self, tag: u32) -> Option<&Bytes> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v)
+    }
+
+    pub fn insert(&mut self, tag: u32, data: Bytes) {
+        match self.fields.binary_search_by_key(&tag, |(t, _)| *t) {
+            Ok(idx) => self.fields[idx].1 = data,
+            Err(idx) => self.fields.insert(idx, (tag, data)),
+        }
+    }
+
+    pub fn remove(&mut self, tag: u32) -> Option<Bytes> {
+        let idx = self.fields.iter().position(|(t, _)| *t == tag)?;
+        Some(self.fields.remove(idx).1)
+    }
+
+    /// Removes the tag and decodes its value, tagged values always use the compact encoding.
+    pub fn take<T: Wired>(&mut self, tag: u32, version: usize) -> Result<Option<T>, Error> {
+        match self.remove(tag) {
+            None => Ok(None),
+            Some(mut data) => {
+                let value = T::from_wire_compact(&mut WireRead {
+                    version,
+                    buffer: &mut data,
+                })?;
+                Ok(Some(value))
+            }
+        }
+    }
+
+    /// Encodes the value with the compact encoding and stores it under the tag.
+    pub fn put<T: Wired>(&mut self, tag: u32, value: &T, version: usize) {
+        let mut data = BytesMut::new();
+        value.to_wire_compact(&mut WireWrite {
+            version,
+            buffer: &mut data,
+        });
+        self.insert(tag, data.freeze());
     }
 }
 
 impl Wired for TagBuffer {
     fn to_wire(&self, wire: &mut WireWrite) {
-        uvint::from(0).to_wire(wire)
+        uvint::from(self.fields.len()).to_wire(wire);
+        for (tag, data) in &self.fields {
+            uvint::from(*tag as usize).to_wire(wire);
+            uvint::from(data.len()).to_wire(wire);
+            wire.buffer.put_slice(data);
+        }
     }
 
     fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
-        let len = uvint::from_wire(wire)?.0 as usize;
-        let res = TagBuffer {};
-        for i in 0..len {
-            let elem_tag = uvint::from_wire(wire)?;
-            let elem_len = uvint::from_wire(wire)?;
+        let len = uvint::from_wire(wire)?.0;
+        let mut res = TagBuffer::default();
+        for _ in 0..len {
+            let tag = uvint::from_wire(wire)?.0 as u32;
+            let len = uvint::from_wire(wire)?.0;
+            res.insert(tag, wire.take(len)?);
         }
 
         Ok(res)
     }
 }
 
-#[derive(Wired)]
+/// Varint length-prefixed byte array, where a length of -1 encodes null.
+fn put_var_bytes(wire: &mut WireWrite, data: Option<&[u8]>) {
+    match data {
+        None => vint(-1).to_wire(wire),
+        Some(data) => {
+            vint(data.len() as i32).to_wire(wire);
+            wire.buffer.put(data);
+        }
+    }
+}
+
+fn get_var_bytes(wire: &mut WireRead) -> Result<Option<Bytes>, Error> {
+    let len = vint::from_wire(wire)?.0;
+    if len < 0 {
+        return Ok(None);
+    }
+    wire.take(len as usize).map(Some)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RecordHeader {
-    key: String,
-    value: Bytes,
+    pub key: String,
+    pub value: Option<Bytes>,
 }
 
-#[derive(Wired)]
+impl Wired for RecordHeader {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        put_var_bytes(wire, Some(self.key.as_bytes()));
+        put_var_bytes(wire, self.value.as_deref());
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        let key = get_var_bytes(wire)?.ok_or_else(|| wire.error(ErrorKind::InvalidLength(-1)))?;
+        let key = String::from_utf8(key.to_vec()).map_err(|_| wire.error(ErrorKind::InvalidUtf8))?;
+        let value = get_var_bytes(wire)?;
+        Ok(Self { key, value })
+    }
+}
+
+/// Single record inside of a v2 [RecordBatch].
+///
+/// The varint length prefix of the record is computed during serialization.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Record {
-    len: vint,
-    attrs: i8,
-    timestamp_delta: vint,
-    offset_delta: vint,
-    key: Bytes,
-    value: Bytes,
-    headers: Vec<String>,
+    pub attrs: i8,
+    pub timestamp_delta: i64,
+    pub offset_delta: i32,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+    pub headers: Vec<RecordHeader>,
+}
+
+impl Wired for Record {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        let mut body = BytesMut::new();
+        let mut inner = WireWrite {
+            version: wire.version,
+            buffer: &mut body,
+        };
+        self.attrs.to_wire(&mut inner);
+        vlong(self.timestamp_delta).to_wire(&mut inner);
+        vint(self.offset_delta).to_wire(&mut inner);
+        put_var_bytes(&mut inner, self.key.as_deref());
+        put_var_bytes(&mut inner, self.value.as_deref());
+        vint(self.headers.len() as i32).to_wire(&mut inner);
+        for header in &self.headers {
+            header.to_wire(&mut inner);
+        }
+
+        vint(body.len() as i32).to_wire(wire);
+        wire.buffer.put(body);
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        let len = vint::from_wire(wire)?.0;
+        if len < 0 {
+            return Err(wire.error(ErrorKind::InvalidLength(len as i64)));
+        }
+        let mut body = wire.take(len as usize)?;
+        let mut inner = WireRead {
+            version: wire.version,
+            buffer: &mut body,
+        };
+
+        let attrs = i8::from_wire(&mut inner)?;
+        let timestamp_delta = vlong::from_wire(&mut inner)?.0;
+        let offset_delta = vint::from_wire(&mut inner)?.0;
+        let key = get_var_bytes(&mut inner)?;
+        let value = get_var_bytes(&mut inner)?;
+        let count = vint::from_wire(&mut inner)?.0;
+        let headers = inner.items(count.max(0) as usize, RecordHeader::from_wire)?;
+
+        Ok(Self {
+            attrs,
+            timestamp_delta,
+            offset_delta,
+            key,
+            value,
+            headers,
+        })
+    }
 }
 
-#[derive(Wired)]
+/// Size of the `first_offset` and `len` fields preceding the rest of a batch.
+pub const BATCH_LOG_OVERHEAD: usize = 12;
+/// Size of the full v2 batch header, up to and including the record count.
+pub const BATCH_HEADER_SIZE: usize = 61;
+
+/// A v2 (magic 2) record batch.
+///
+/// The `len` and `crc` fields of the wire format are computed during serialization,
+/// use [RecordBatchBuilder] to also compute offset and timestamp deltas.
+///
+/// Batches whose codec is unknown or not compiled in are written uncompressed,
+/// requests carrying them fail [RecordSet::validate] before they are sent.
+#[derive(Debug, Clone, PartialEq)]
 pub struct RecordBatch {
+    pub first_offset: i64,
+    pub part_leader_epoch: i32,
+    pub magic: i8,
+    pub attrs: i16,
+    pub last_offset_delta: i32,
+    pub first_timestamp: i64,
+    pub max_timestamp: i64,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub first_sequence: i32,
+    pub records: Vec<Record>,
+}
+
+impl Wired for RecordBatch {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        let start = wire.buffer.len();
+        self.first_offset.to_wire(wire);
+        // Length placeholder
+        0i32.to_wire(wire);
+        self.part_leader_epoch.to_wire(wire);
+        self.magic.to_wire(wire);
+        let crc_pos = wire.buffer.len();
+        // CRC placeholder
+        0u32.to_wire(wire);
+        let codec = self.compression().unwrap_or_default();
+        ((self.attrs & !Compression::ATTR_MASK) | codec.id()).to_wire(wire);
+        self.last_offset_delta.to_wire(wire);
+        self.first_timestamp.to_wire(wire);
+        self.max_timestamp.to_wire(wire);
+        self.producer_id.to_wire(wire);
+        self.producer_epoch.to_wire(wire);
+        self.first_sequence.to_wire(wire);
+        (self.records.len() as i32).to_wire(wire);
+        if codec == Compression::None {
+            for record in &self.records {
+                record.to_wire(wire);
+            }
+        } else {
+            let mut records = BytesMut::new();
+            let mut inner = WireWrite {
+                version: wire.version,
+                buffer: &mut records,
+            };
+            for record in &self.records {
+                record.to_wire(&mut inner);
+            }
+            wire.buffer.put_slice(&codec.compress(&records));
+        }
+
+        let len = wire.buffer.len() - start - BATCH_LOG_OVERHEAD;
+        let crc = crc32c::crc32c(&wire.buffer[crc_pos + 4..]);
+        BigEndian::write_i32(&mut wire.buffer[start + 8..], len as i32);
+        BigEndian::write_u32(&mut wire.buffer[crc_pos..], crc);
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        let version = wire.version;
+        RecordBatch::read_checked(wire).map_err(|c| Error::new(ErrorKind::Corrupt(c.reason), version))
+    }
+}
+
+impl RecordBatch {
+    /// Decodes a batch, verifying its length, magic and CRC32C.
+    pub fn read_checked(wire: &mut WireRead) -> Result<Self, CorruptBatch> {
+        let first_offset = i64::from_wire(wire).map_err(|_| CorruptBatch::new(-1, Corruption::Truncated))?;
+        let corrupt = |reason| CorruptBatch::new(first_offset, reason);
+
+        let len = i32::from_wire(wire).map_err(|_| corrupt(Corruption::Truncated))?;
+        if len < (BATCH_HEADER_SIZE - BATCH_LOG_OVERHEAD) as i32 {
+            return Err(corrupt(Corruption::InvalidLength(len)));
+        }
+        if len as usize > wire.buffer.len() {
+            return Err(corrupt(Corruption::Truncated));
+        }
+        let version = wire.version;
+        let mut body = wire.buffer.split_to(len as usize);
+        let mut wire = WireRead {
+            version,
+            buffer: &mut body,
+        };
+
+        let part_leader_epoch = BigEndian::read_i32(&wire.buffer[0..]);
+        let magic = wire.buffer[4] as i8;
+        if magic != 2 {
+            return Err(corrupt(Corruption::InvalidMagic(magic)));
+        }
+        let expected = BigEndian::read_u32(&wire.buffer[5..]);
+        let computed = crc32c::crc32c(&wire.buffer[9..]);
+        if expected != computed {
+            return Err(corrupt(Corruption::CrcMismatch { expected, computed }));
+        }
+        wire.buffer.advance(9);
+
+        let mut batch = Self {
+            first_offset,
+            part_leader_epoch,
+            magic,
+            attrs: 0,
+            last_offset_delta: 0,
+            first_timestamp: 0,
+            max_timestamp: 0,
+            producer_id: 0,
+            producer_epoch: 0,
+            first_sequence: 0,
+            records: vec![],
+        };
+        let mut read_header = || -> Result<i32, Error> {
+            batch.attrs = i16::from_wire(&mut wire)?;
+            batch.last_offset_delta = i32::from_wire(&mut wire)?;
+            batch.first_timestamp = i64::from_wire(&mut wire)?;
+            batch.max_timestamp = i64::from_wire(&mut wire)?;
+            batch.producer_id = i64::from_wire(&mut wire)?;
+            batch.producer_epoch = i16::from_wire(&mut wire)?;
+            batch.first_sequence = i32::from_wire(&mut wire)?;
+            i32::from_wire(&mut wire)
+        };
+        let count = read_header().map_err(|_| corrupt(Corruption::InvalidRecords))?;
+
+        let codec = batch.compression().map_err(|codec| corrupt(Corruption::UnsupportedCompression(codec)))?;
+        let mut records = if codec == Compression::None {
+            body
+        } else {
+            codec.decompress(&body).map_err(|_| corrupt(Corruption::Decompression))?.into()
+        };
+        let mut wire = WireRead {
+            version,
+            buffer: &mut records,
+        };
+        batch.records = wire.items(count.max(0) as usize, Record::from_wire)
+            .map_err(|_| corrupt(Corruption::InvalidRecords))?;
+        Ok(batch)
+    }
+
+    pub fn compression(&self) -> Result<Compression, u8> {
+        Compression::from_attrs(self.attrs)
+    }
+}
+
+/// Reason for rejecting a record batch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Corruption {
+    /// The batch ended before all of its declared content could be read.
+    Truncated,
+    /// The batch length is negative or smaller than the batch header.
+    InvalidLength(i32),
+    InvalidMagic(i8),
+    CrcMismatch { expected: u32, computed: u32 },
+    /// The batch header is valid, but its records could not be decoded.
+    InvalidRecords,
+    /// The batch is compressed with a codec which is unknown or was not enabled as a cargo feature.
+    UnsupportedCompression(u8),
+    Decompression,
+    /// A compressed legacy wrapper message contains another compressed message.
+    NestedCompression,
+}
+
+impl Display for Corruption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Corruption::Truncated => write!(f, "batch is truncated"),
+            Corruption::InvalidLength(len) => write!(f, "invalid batch length {}", len),
+            Corruption::InvalidMagic(magic) => write!(f, "invalid magic {}", magic),
+            Corruption::CrcMismatch { expected, computed } => {
+                write!(f, "crc mismatch, expected {:#010x}, computed {:#010x}", expected, computed)
+            }
+            Corruption::InvalidRecords => write!(f, "records could not be decoded"),
+            Corruption::UnsupportedCompression(codec) => write!(f, "unsupported compression codec {}", codec),
+            Corruption::Decompression => write!(f, "records could not be decompressed"),
+            Corruption::NestedCompression => write!(f, "compressed message nested in a compressed message"),
+        }
+    }
+}
+
+/// A batch which failed validation, identified by its base offset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptBatch {
+    pub base_offset: i64,
+    pub reason: Corruption,
+}
+
+impl CorruptBatch {
+    fn new(base_offset: i64, reason: Corruption) -> Self {
+        Self { base_offset, reason }
+    }
+}
+
+/// Corresponds to [crate::KafkaCode::CorruptMessage], raised by the client when fetched data fails validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptMessage {
+    pub topic: String,
+    pub partition: i32,
+    pub base_offset: i64,
+    pub reason: Corruption,
+}
+
+impl Display for CorruptMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Corrupt record batch at offset {} of {}-{}: {}", self.base_offset, self.topic, self.partition, self.reason)
+    }
+}
+
+impl std::error::Error for CorruptMessage {}
+
+/// Builds a v2 [RecordBatch], computing offset deltas, timestamp deltas and the batch timestamps.
+#[derive(Debug, Clone)]
+pub struct RecordBatchBuilder {
     first_offset: i64,
-    len: i32,
     part_leader_epoch: i32,
-    magic: i8,
-    crc: i32,
     attrs: i16,
-    last_offset_delta: i32,
-    first_timestamp: i64,
-    max_timestamp: i64,
     producer_id: i64,
     producer_epoch: i16,
     first_sequence: i32,
-    records: Vec<Record>,
+    records: Vec<(i64, Record)>,
+}
+
+impl Default for RecordBatchBuilder {
+    fn default() -> Self {
+        Self {
+            first_offset: 0,
+            part_leader_epoch: -1,
+            attrs: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            first_sequence: -1,
+            records: vec![],
+        }
+    }
+}
+
+impl RecordBatchBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn first_offset(mut self, offset: i64) -> Self {
+        self.first_offset = offset;
+        self
+    }
+
+    pub fn compression(mut self, codec: Compression) -> Self {
+        self.attrs = (self.attrs & !Compression::ATTR_MASK) | codec.id();
+        self
+    }
+
+    pub fn part_leader_epoch(mut self, epoch: i32) -> Self {
+        self.part_leader_epoch = epoch;
+        self
+    }
+
+    /// Sets the idempotent producer identity, used for deduplication on the broker.
+    pub fn producer(mut self, id: i64, epoch: i16, first_sequence: i32) -> Self {
+        self.producer_id = id;
+        self.producer_epoch = epoch;
+        self.first_sequence = first_sequence;
+        self
+    }
+
+    /// Appends a record, `timestamp` is in milliseconds since unix epoch.
+    pub fn record(
+        mut self,
+        timestamp: i64,
+        key: Option<Bytes>,
+        value: Option<Bytes>,
+        headers: Vec<RecordHeader>,
+    ) -> Self {
+        let record = Record {
+            attrs: 0,
+            timestamp_delta: 0,
+            offset_delta: self.records.len() as i32,
+            key,
+            value,
+            headers,
+        };
+        self.records.push((timestamp, record));
+        self
+    }
+
+    pub fn build(self) -> RecordBatch {
+        let first_timestamp = self.records.first().map(|(ts, _)| *ts).unwrap_or(-1);
+        let max_timestamp = self.records.iter().map(|(ts, _)| *ts).max().unwrap_or(-1);
+
+        RecordBatch {
+            first_offset: self.first_offset,
+            part_leader_epoch: self.part_leader_epoch,
+            magic: 2,
+            attrs: self.attrs,
+            last_offset_delta: self.records.len() as i32 - 1,
+            first_timestamp,
+            max_timestamp,
+            producer_id: self.producer_id,
+            producer_epoch: self.producer_epoch,
+            first_sequence: self.first_sequence,
+            records: self.records.into_iter().map(|(ts, mut record)| {
+                record.timestamp_delta = ts - first_timestamp;
+                record
+            }).collect(),
+        }
+    }
+}
+
+/// Attribute bit set when the broker overwrote the record timestamps with its log append time.
+const TIMESTAMP_TYPE_MASK: i16 = 0x08;
+
+/// Record with its absolute offset and timestamp, independent of the format it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchedRecord {
+    pub offset: i64,
+    /// Milliseconds since unix epoch, -1 for magic 0 messages which carry no timestamp.
+    pub timestamp: i64,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+    pub headers: Vec<RecordHeader>,
+}
+
+/// Single entry of a [RecordSet], the format is selected by the magic byte.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Batch {
+    /// Magic 0 or 1 message, compressed wrapper messages are expanded into their inner messages.
+    Legacy(Vec<Message>),
+    V2(RecordBatch),
+}
+
+impl Batch {
+    pub fn records(&self) -> Box<dyn Iterator<Item=FetchedRecord> + '_> {
+        match self {
+            Batch::Legacy(messages) => Box::new(messages.iter().map(|m| FetchedRecord {
+                offset: m.offset,
+                timestamp: m.timestamp.unwrap_or(-1),
+                key: m.key.clone(),
+                value: m.value.clone(),
+                headers: vec![],
+            })),
+            Batch::V2(batch) => Box::new(batch.records.iter().map(move |r| FetchedRecord {
+                offset: batch.first_offset + r.offset_delta as i64,
+                timestamp: if batch.attrs & TIMESTAMP_TYPE_MASK != 0 {
+                    batch.max_timestamp
+                } else {
+                    batch.first_timestamp + r.timestamp_delta
+                },
+                key: r.key.clone(),
+                value: r.value.clone(),
+                headers: r.headers.clone(),
+            })),
+        }
+    }
+}
+
+/// The `RECORDS` protocol type, a size-prefixed sequence of record batches or legacy messages.
+///
+/// Brokers may return a partial batch at the end of a fetch response, it is dropped while decoding.
+/// Decoding stops at the first batch which fails validation, the batches preceding it are kept
+/// and the failure is stored in `corrupt`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordSet {
+    pub batches: Vec<Batch>,
+    pub corrupt: Option<CorruptBatch>,
+}
+
+impl RecordSet {
+    /// Records of all batches in offset order, regardless of their format.
+    pub fn records(&self) -> impl Iterator<Item=FetchedRecord> + '_ {
+        self.batches.iter().flat_map(|b| b.records())
+    }
+}
+
+impl RecordSet {
+    /// Fails for batches whose compression codec is unknown or not compiled in.
+    pub fn validate(&self) -> crate::Result<()> {
+        for batch in &self.batches {
+            if let Batch::V2(batch) = batch {
+                batch.compression().map_err(|codec| crate::Error::Encode(format!(
+                    "Record batch at offset {} uses unsupported compression codec {}", batch.first_offset, codec
+                )))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<RecordBatch> for RecordSet {
+    fn from(batch: RecordBatch) -> Self {
+        Self { batches: vec![Batch::V2(batch)], corrupt: None }
+    }
+}
+
+impl Wired for RecordSet {
+    fn to_wire(&self, wire: &mut WireWrite) {
+        let start = wire.buffer.len();
+        // Length placeholder
+        0i32.to_wire(wire);
+        for batch in &self.batches {
+            match batch {
+                Batch::Legacy(messages) => messages.iter().for_each(|m| m.write_entry(wire)),
+                Batch::V2(batch) => batch.to_wire(wire),
+            }
+        }
+        let len = wire.buffer.len() - start - 4;
+        BigEndian::write_i32(&mut wire.buffer[start..], len as i32);
+    }
+
+    fn from_wire(wire: &mut WireRead) -> Result<Self, Error> {
+        let len = i32::from_wire(wire)?;
+        if len < 0 {
+            return Ok(Self::default());
+        }
+        let mut data = wire.take(len as usize)?;
+        let mut res = Self::default();
+        while data.len() >= BATCH_LOG_OVERHEAD {
+            let batch_len = BigEndian::read_i32(&data[8..]);
+            if batch_len >= 0 && data.len() < BATCH_LOG_OVERHEAD + batch_len as usize {
+                break;
+            }
+            // Both formats place the magic byte at the same position
+            let magic = data.get(BATCH_LOG_OVERHEAD + 4).cloned().unwrap_or(2) as i8;
+            let mut wire = WireRead {
+                version: wire.version,
+                buffer: &mut data,
+            };
+            let batch = if magic < 2 {
+                Message::read_entry(&mut wire).map(Batch::Legacy)
+            } else {
+                RecordBatch::read_checked(&mut wire).map(Batch::V2)
+            };
+            match batch {
+                Ok(batch) => res.batches.push(batch),
+                Err(corrupt) => {
+                    res.corrupt = Some(corrupt);
+                    break;
+                }
+            }
+        }
+        Ok(res)
+    }
 }
 
 #[derive(Debug, Clone, Wired)]
@@ -248,3 +927,299 @@ pub struct TopicMap<T: Wired> {
 }
 
 
+
+#[test]
+fn test_varint_roundtrip() {
+    let cases: &[(i64, &[u8])] = &[
+        (0, &[0x00]),
+        (-1, &[0x01]),
+        (1, &[0x02]),
+        (-64, &[0x7f]),
+        (64, &[0x80, 0x01]),
+        (300, &[0xd8, 0x04]),
+    ];
+    for (val, enc) in cases {
+        let mut buf = BytesMut::new();
+        vint(*val as i32).to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+        assert_eq!(&buf[..], *enc);
+
+        let mut buf = BytesMut::new();
+        vlong(*val).to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+        assert_eq!(&buf[..], *enc);
+
+        let mut data = buf.freeze();
+        assert_eq!(vlong::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap().0, *val);
+    }
+
+    for val in &[i32::MIN, i32::MAX] {
+        let mut buf = BytesMut::new();
+        vint(*val).to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+        assert_eq!(buf.len(), 5);
+        let mut data = buf.freeze();
+        assert_eq!(vint::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap().0, *val);
+    }
+
+    for val in &[i64::MIN, i64::MAX] {
+        let mut buf = BytesMut::new();
+        vlong(*val).to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+        assert_eq!(buf.len(), 10);
+        let mut data = buf.freeze();
+        assert_eq!(vlong::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap().0, *val);
+    }
+}
+
+#[test]
+fn test_record_roundtrip() {
+    let record = Record {
+        attrs: 0,
+        timestamp_delta: 12,
+        offset_delta: 3,
+        key: None,
+        value: Some(Bytes::from_static(b"value")),
+        headers: vec![RecordHeader {
+            key: "h".to_string(),
+            value: None,
+        }],
+    };
+
+    let mut buf = BytesMut::new();
+    record.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    assert_eq!(&buf[..], &[
+        0x1c, // length: 14
+        0x00, // attributes
+        0x18, // timestamp delta: 12
+        0x06, // offset delta: 3
+        0x01, // key: null
+        0x0a, b'v', b'a', b'l', b'u', b'e',
+        0x02, // header count: 1
+        0x02, b'h',
+        0x01, // header value: null
+    ][..]);
+
+    let mut data = buf.freeze();
+    let decoded = Record::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded, record);
+    assert!(data.is_empty());
+}
+
+#[test]
+fn test_record_batch_builder() {
+    let batch = RecordBatchBuilder::new()
+        .record(1000, Some(Bytes::from_static(b"k")), Some(Bytes::from_static(b"v1")), vec![])
+        .record(1005, None, Some(Bytes::from_static(b"v2")), vec![])
+        .record(990, None, None, vec![])
+        .build();
+
+    assert_eq!(batch.last_offset_delta, 2);
+    assert_eq!(batch.first_timestamp, 1000);
+    assert_eq!(batch.max_timestamp, 1005);
+    assert_eq!(batch.records[1].offset_delta, 1);
+    assert_eq!(batch.records[1].timestamp_delta, 5);
+    assert_eq!(batch.records[2].timestamp_delta, -10);
+
+    let mut buf = BytesMut::new();
+    batch.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+
+    assert_eq!(BigEndian::read_i32(&buf[8..]) as usize, buf.len() - BATCH_LOG_OVERHEAD);
+    assert_eq!(buf[16], 2);
+    assert_eq!(BigEndian::read_u32(&buf[17..]), crc32c::crc32c(&buf[21..]));
+
+    let mut data = buf.freeze();
+    let decoded = RecordBatch::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded, batch);
+}
+
+#[test]
+fn test_record_set_validation() {
+    let batch = |offset| RecordBatchBuilder::new()
+        .first_offset(offset)
+        .record(1000, None, Some(Bytes::from_static(b"value")), vec![])
+        .build();
+
+    let set = RecordSet { batches: vec![Batch::V2(batch(0)), Batch::V2(batch(1)), Batch::V2(batch(2))], corrupt: None };
+    let mut buf = BytesMut::new();
+    set.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    let batch_len = (buf.len() - 4) / 3;
+
+    // Partial trailing batch is dropped
+    let mut partial = BytesMut::from(&buf[..buf.len() - 10]);
+    let len = partial.len() - 4;
+    BigEndian::write_i32(&mut partial[..], len as i32);
+    let mut data = partial.freeze();
+    let decoded = RecordSet::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded.batches, set.batches[..2]);
+    assert_eq!(decoded.corrupt, None);
+
+    // Flipped bit in the value of the second batch
+    let mut corrupted = buf.clone();
+    let last = 4 + 2 * batch_len - 2;
+    corrupted[last] ^= 1;
+    let mut data = corrupted.freeze();
+    let decoded = RecordSet::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded.batches, set.batches[..1]);
+    let corrupt = decoded.corrupt.unwrap();
+    assert_eq!(corrupt.base_offset, 1);
+    assert!(matches!(corrupt.reason, Corruption::CrcMismatch { .. }));
+
+    // Unknown magic
+    let mut corrupted = buf.clone();
+    corrupted[4 + 16] = 7;
+    let mut data = corrupted.freeze();
+    let decoded = RecordSet::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert!(decoded.batches.is_empty());
+    assert_eq!(decoded.corrupt, Some(CorruptBatch::new(0, Corruption::InvalidMagic(7))));
+
+    // Impossible length
+    let mut corrupted = buf;
+    BigEndian::write_i32(&mut corrupted[4 + 8..], 3);
+    let mut data = corrupted.freeze();
+    let decoded = RecordSet::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded.corrupt, Some(CorruptBatch::new(0, Corruption::InvalidLength(3))));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_compressed_batch_roundtrip() {
+    let batch = RecordBatchBuilder::new()
+        .compression(Compression::Gzip)
+        .record(1000, None, Some(Bytes::from_static(b"compressed")), vec![])
+        .record(1001, None, Some(Bytes::from_static(b"compressed")), vec![])
+        .build();
+    assert_eq!(batch.compression(), Ok(Compression::Gzip));
+
+    let mut buf = BytesMut::new();
+    batch.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    assert_eq!(BigEndian::read_u32(&buf[17..]), crc32c::crc32c(&buf[21..]));
+
+    let mut data = buf.freeze();
+    let decoded = RecordBatch::read_checked(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded, batch);
+}
+
+#[test]
+fn test_unsupported_codec_encoding() {
+    let mut batch = RecordBatchBuilder::new()
+        .record(1000, None, Some(Bytes::from_static(b"value")), vec![])
+        .build();
+    batch.attrs = 5;
+    let set = RecordSet::from(batch.clone());
+    assert_eq!(
+        set.validate().unwrap_err().to_string(),
+        "Invalid request: Record batch at offset 0 uses unsupported compression codec 5"
+    );
+
+    // Written uncompressed instead of panicking
+    let mut buf = BytesMut::new();
+    batch.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    let mut data = buf.freeze();
+    let decoded = RecordBatch::read_checked(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded.compression(), Ok(Compression::None));
+    assert_eq!(decoded.records, batch.records);
+}
+
+#[test]
+fn test_mixed_record_set() {
+    let legacy = Message {
+        offset: 9,
+        magic: 1,
+        attrs: 0,
+        timestamp: Some(900),
+        key: None,
+        value: Some(Bytes::from_static(b"legacy")),
+    };
+    let batch = RecordBatchBuilder::new()
+        .first_offset(10)
+        .record(1000, None, Some(Bytes::from_static(b"a")), vec![])
+        .record(1001, None, Some(Bytes::from_static(b"b")), vec![])
+        .build();
+    let set = RecordSet { batches: vec![Batch::Legacy(vec![legacy]), Batch::V2(batch)], corrupt: None };
+
+    let mut buf = BytesMut::new();
+    set.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    let mut data = buf.freeze();
+    let decoded = RecordSet::from_wire(&mut WireRead { version: 0, buffer: &mut data }).unwrap();
+    assert_eq!(decoded, set);
+
+    let records = decoded.records().map(|r| (r.offset, r.timestamp)).collect::<Vec<_>>();
+    assert_eq!(records, vec![(9, 900), (10, 1000), (11, 1001)]);
+}
+
+#[test]
+fn test_truncated_decode() {
+    let item = TopicItem { topic: "topic".to_string(), value: vec!["a".to_string(), "bc".to_string()] };
+    let mut buf = BytesMut::new();
+    item.to_wire(&mut WireWrite { version: 3, buffer: &mut buf });
+    let data = buf.freeze();
+
+    for len in 0..data.len() {
+        let mut part = data.slice(..len);
+        assert!(TopicItem::<String>::from_wire(&mut WireRead { version: 3, buffer: &mut part }).is_err());
+    }
+
+    let mut part = data.slice(..data.len() - 1);
+    let err = TopicItem::<String>::from_wire(&mut WireRead { version: 3, buffer: &mut part }).unwrap_err();
+    assert_eq!(err.path, vec!["value"]);
+    assert_eq!(err.kind, ErrorKind::Truncated { expected: 2, remaining: 1 });
+    assert_eq!(err.version, 3);
+    assert_eq!(err.to_string(), "Decoding `value` of version 3 failed: expected 2 bytes, but only 1 remain");
+
+    let mut invalid = Bytes::from_static(&[0x00, 0x02, 0xff, 0xfe]);
+    let err = String::from_wire(&mut WireRead { version: 0, buffer: &mut invalid }).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidUtf8);
+
+    let mut invalid = Bytes::from_static(&[0x80; 11]);
+    let err = vlong::from_wire(&mut WireRead { version: 0, buffer: &mut invalid }).map(|v| v.0).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidVarint);
+
+    // Huge array lengths must not be trusted for preallocation
+    let mut huge = Bytes::from_static(&[0x7f, 0xff, 0xff, 0xff]);
+    assert!(Vec::<RecordBatch>::from_wire(&mut WireRead { version: 0, buffer: &mut huge }).is_err());
+}
+
+#[test]
+fn test_wire_enums() {
+    let mut data = Bytes::from_static(&[0x00, 0x12, 0x00, 0x3c, 0x02]);
+    let mut wire = WireRead { version: 0, buffer: &mut data };
+    assert_eq!(ApiKey::from_wire(&mut wire).unwrap(), ApiKey::ApiVersions);
+    assert_eq!(ApiKey::from_wire(&mut wire).unwrap(), ApiKey::Unknown(60));
+    assert_eq!(IsolationLevel::from_wire(&mut wire).unwrap(), IsolationLevel::Unknown(2));
+
+    let mut buf = BytesMut::new();
+    ApiKey::Unknown(60).to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    ApiKey::AlterClientQuotas.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    IsolationLevel::ReadCommited.to_wire(&mut WireWrite { version: 0, buffer: &mut buf });
+    assert_eq!(&buf[..], &[0x00, 0x3c, 0x00, 0x31, 0x01]);
+
+    assert_eq!(i16::from(crate::KafkaCode::UnknownServerError), -1);
+    assert_eq!(crate::KafkaCode::from(88), crate::KafkaCode::UnstableOffsetCommit);
+    assert_eq!(crate::KafkaCode::from(1000), crate::KafkaCode::Unknown(1000));
+}
+
+#[test]
+fn test_partition_errors() {
+    use crate::KafkaCode;
+    use crate::proto::list_offsets::{self, ListOffsetsPartitionResponse, ListOffsetsTopicResponse};
+
+    let part = |partition_index, error_code| ListOffsetsPartitionResponse {
+        partition_index,
+        error_code,
+        ..Default::default()
+    };
+    let res = list_offsets::Response {
+        topics: vec![ListOffsetsTopicResponse {
+            name: "topic".to_string(),
+            partitions: vec![part(0, 0), part(1, 6), part(2, 300)],
+        }],
+        ..Default::default()
+    };
+    let errors = res.errors().map(|(topic, p, e)| (topic, p.partition_index, e.code)).collect::<Vec<_>>();
+    assert_eq!(errors, vec![("topic", 1, KafkaCode::NotLeaderOrFollower), ("topic", 2, KafkaCode::Unknown(300))]);
+
+    assert!(KafkaCode::NotLeaderOrFollower.is_retriable());
+    assert!(KafkaCode::NotLeaderOrFollower.requires_metadata_refresh());
+    assert!(!KafkaCode::TopicAuthorizationFailed.is_retriable());
+    assert!(!KafkaCode::Unknown(300).is_retriable());
+
+    let err = KafkaError::from_code(87, Some("Compacted topic cannot accept message without key")).unwrap_err();
+    assert_eq!(err.to_string(), "InvalidRecord (87): Compacted topic cannot accept message without key");
+}
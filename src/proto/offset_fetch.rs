@@ -1,44 +1,10 @@
-use crate::proto::{TopicMap, TagBuffer, ApiRequest, ApiKey};
+use crate::proto::ErrorCode;
 
-impl ApiRequest for Request {
-    const API_KEY: ApiKey = ApiKey::OffsetFetch;
-    const FLEXIBLE_VER: usize = 6;
-    type Response = Response;
-}
-#[derive(Debug, Wired)]
-pub struct Request {
-    group_id: String,
-    values: TopicMap<i32>,
-    #[wired(since = 7)]
-    require_stable: Option<bool>,
-
-
-    #[wired(since = 6)]
-    tags: Option<TagBuffer>,
-}
+// Generated from schemas/OffsetFetchRequest.json and schemas/OffsetFetchResponse.json
+kafka_schema!("OffsetFetch");
 
-#[derive(Debug, Wired)]
-pub struct RespPartData {
-    partition_index: i32,
-    commited_offset: i64,
-    #[wired(since = 5)]
-    commited_leader_epoch: Option<i32>,
-    metadata: Option<String>,
-    error_code: i16,
-
-    #[wired(since = 6)]
-    tags: Option<TagBuffer>,
+impl ErrorCode for OffsetFetchResponsePartition {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
 }
-
-#[derive(Debug, Wired)]
-pub struct Response {
-    #[wired(since = 3)]
-    throttle_time_ms: Option<i32>,
-    topics: TopicMap<RespPartData>,
-
-    #[wired(since = 2)]
-    error_code: Option<i16>,
-
-    #[wired(since = 6)]
-    tags: Option<TagBuffer>,
-}
\ No newline at end of file
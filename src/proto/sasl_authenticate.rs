@@ -0,0 +1,14 @@
+use crate::proto::ErrorCode;
+
+// Generated from schemas/SaslAuthenticateRequest.json and schemas/SaslAuthenticateResponse.json
+kafka_schema!("SaslAuthenticate");
+
+impl ErrorCode for Response {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
+
+    fn error_message(&self) -> Option<&str> {
+        self.error_message.as_deref()
+    }
+}
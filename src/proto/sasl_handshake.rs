@@ -0,0 +1,10 @@
+use crate::proto::ErrorCode;
+
+// Generated from schemas/SaslHandshakeRequest.json and schemas/SaslHandshakeResponse.json
+kafka_schema!("SaslHandshake");
+
+impl ErrorCode for Response {
+    fn error_code(&self) -> i16 {
+        self.error_code
+    }
+}
@@ -0,0 +1,288 @@
+//! SASL authentication of connections, performed after `ApiVersions` when a SASL [SecurityConfig] is selected.
+//!
+//! Brokers expire sessions after the `session_lifetime_ms` returned by `SaslAuthenticate` (KIP-368),
+//! [Session] tracks the deadline so the client can re-authenticate over the same connection before it passes.
+//!
+//! [SecurityConfig]: crate::config::SecurityConfig
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use rand::Rng;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::{Service, ServiceExt};
+
+use crate::config::{SaslConfig, SaslMechanism};
+use crate::proto::{negotiate_version, sasl_authenticate, sasl_handshake, ApiKey, ApiRequest, ErrorCode, TagBuffer};
+use crate::transport::{CallReq, TypedClient};
+use crate::{KafkaCode, KafkaError};
+
+mod oauthbearer;
+mod scram;
+
+pub use oauthbearer::{OAuthToken, TokenError, TokenProvider, UnsecuredJwsProvider};
+
+#[derive(Debug)]
+pub enum SaslError {
+    /// The broker does not enable the configured mechanism.
+    UnsupportedMechanism {
+        mechanism: &'static str,
+        enabled: Vec<String>,
+    },
+    /// The broker rejected the credentials, usually with `SaslAuthenticationFailed` and a message.
+    Failed(KafkaError),
+    /// The broker does not support the requests required for authentication.
+    UnsupportedBroker(&'static str),
+    /// The configuration lacks settings required by the mechanism.
+    InvalidConfig(&'static str),
+    /// Messages of the broker do not follow the mechanism.
+    Protocol(String),
+    /// The [TokenProvider] failed to supply a token.
+    Token(TokenError),
+}
+
+impl fmt::Display for SaslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaslError::UnsupportedMechanism { mechanism, enabled } => write!(
+                f, "SASL mechanism {} is not enabled by the broker, enabled mechanisms: {}",
+                mechanism, enabled.join(", ")
+            ),
+            SaslError::Failed(err) => write!(f, "SASL authentication failed: {}", err),
+            SaslError::UnsupportedBroker(msg) => write!(f, "SASL authentication is not supported: {}", msg),
+            SaslError::InvalidConfig(msg) => write!(f, "Invalid SASL configuration: {}", msg),
+            SaslError::Protocol(msg) => write!(f, "SASL exchange failed: {}", msg),
+            SaslError::Token(err) => write!(f, "Failed to obtain an OAUTHBEARER token: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaslError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaslError::Failed(err) => Some(err),
+            SaslError::Token(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Client side of a SASL exchange.
+pub(crate) trait Mechanism: Send {
+    /// Produces the next message from the last message of the broker, which is `None` before the first one.
+    /// Returns `None` once the exchange is complete.
+    fn step(&mut self, challenge: Option<&[u8]>) -> Result<Option<Vec<u8>>, SaslError>;
+}
+
+/// PLAIN from RFC 4616, a single message carrying the credentials.
+struct Plain {
+    message: Option<Vec<u8>>,
+}
+
+impl Mechanism for Plain {
+    fn step(&mut self, challenge: Option<&[u8]>) -> Result<Option<Vec<u8>>, SaslError> {
+        match challenge {
+            None => Ok(self.message.take()),
+            Some(_) => Ok(None),
+        }
+    }
+}
+
+/// Creates the mechanism, along with the expiry of the credentials it presents.
+async fn mechanism(config: &SaslConfig) -> crate::Result<(Box<dyn Mechanism>, Option<SystemTime>)> {
+    let res: Box<dyn Mechanism> = match config.mechanism {
+        SaslMechanism::Plain => Box::new(Plain {
+            message: Some(format!("\0{}\0{}", config.username, config.password).into_bytes()),
+        }),
+        SaslMechanism::ScramSha256 => Box::new(scram::Scram::new(scram::Hash::Sha256, &config.username, &config.password)),
+        SaslMechanism::ScramSha512 => Box::new(scram::Scram::new(scram::Hash::Sha512, &config.username, &config.password)),
+        SaslMechanism::OAuthBearer => {
+            let provider = config.token_provider.as_ref().ok_or(SaslError::InvalidConfig(MISSING_PROVIDER))?;
+            let token = provider.token().await.map_err(SaslError::Token)?;
+            let expires_at = token.expires_at;
+            return Ok((Box::new(oauthbearer::OAuthBearer::new(token)), Some(expires_at)));
+        }
+    };
+    Ok((res, None))
+}
+
+const MISSING_PROVIDER: &str = "OAUTHBEARER requires a token provider";
+
+/// Authenticated session of a connection.
+pub(crate) struct Session {
+    config: SaslConfig,
+    handshake_version: usize,
+    authenticate_version: usize,
+    /// Re-authentication deadline, `None` when the broker does not expire sessions.
+    reauthenticate_at: Mutex<Option<Instant>>,
+}
+
+impl Session {
+    /// Selects the request versions, brokers which only support `SaslHandshake` v0 are not supported,
+    /// since they expect raw SASL tokens instead of `SaslAuthenticate` requests.
+    pub(crate) fn new(config: SaslConfig, versions: &HashMap<ApiKey, (usize, usize)>) -> Result<Self, SaslError> {
+        let version = |key, client| versions.get(&key).and_then(|broker| negotiate_version(client, *broker));
+        let handshake_version = version(ApiKey::SaslHandshake, (1, sasl_handshake::Request::VERSIONS.1))
+            .ok_or(SaslError::UnsupportedBroker("SaslHandshake v1 is required"))?;
+        let authenticate_version = version(ApiKey::SaslAuthenticate, sasl_authenticate::Request::VERSIONS)
+            .ok_or(SaslError::UnsupportedBroker("SaslAuthenticate is required"))?;
+        if config.mechanism == SaslMechanism::OAuthBearer && config.token_provider.is_none() {
+            return Err(SaslError::InvalidConfig(MISSING_PROVIDER));
+        }
+
+        Ok(Self {
+            config,
+            handshake_version,
+            authenticate_version,
+            reauthenticate_at: Mutex::new(None),
+        })
+    }
+
+    fn needs_reauthentication(&self) -> bool {
+        matches!(*self.reauthenticate_at.lock().unwrap(), Some(at) if at <= Instant::now())
+    }
+
+    /// Authenticates the connection, no other requests may be in flight.
+    pub(crate) async fn authenticate<T>(&self, client: &mut TypedClient<T>) -> crate::Result<()>
+        where T: AsyncRead + AsyncWrite + Send + 'static
+    {
+        let name = self.config.mechanism.name();
+        let req = sasl_handshake::Request {
+            mechanism: name.to_string(),
+        };
+        let res = call(client, self.handshake_version, req).await?;
+        if KafkaCode::from(res.error_code) == KafkaCode::UnsupportedSaslMechanism {
+            return Err(SaslError::UnsupportedMechanism { mechanism: name, enabled: res.mechanisms }.into());
+        }
+        res.error()?;
+
+        let (mut mechanism, expires_at) = mechanism(&self.config).await?;
+        let mut message = mechanism.step(None)?;
+        let mut lifetime = 0;
+        while let Some(auth_bytes) = message {
+            let req = sasl_authenticate::Request {
+                auth_bytes: auth_bytes.into(),
+                tags: TagBuffer::default().into(),
+            };
+            let res = call(client, self.authenticate_version, req).await?;
+            res.error().map_err(SaslError::Failed)?;
+            lifetime = res.session_lifetime_ms.unwrap_or(0);
+            message = mechanism.step(Some(&res.auth_bytes))?;
+        }
+
+        let mut lifetime = (lifetime > 0).then_some(Duration::from_millis(lifetime as u64));
+        // Brokers may not expire sessions, but expired credentials must still be replaced
+        if let Some(expires_at) = expires_at {
+            let remaining = expires_at.duration_since(SystemTime::now()).unwrap_or_default();
+            lifetime = Some(lifetime.map_or(remaining, |l| l.min(remaining)));
+        }
+        // Same window as the Java client, spreading re-authentication of connections opened together
+        *self.reauthenticate_at.lock().unwrap() = lifetime.map(|lifetime| {
+            Instant::now() + lifetime.mul_f64(rand::thread_rng().gen_range(0.85..0.95))
+        });
+        Ok(())
+    }
+
+    /// Re-authenticates the connection when the session is about to expire.
+    pub(crate) async fn refresh<T>(&self, client: &mut TypedClient<T>) -> crate::Result<()>
+        where T: AsyncRead + AsyncWrite + Send + 'static
+    {
+        if self.needs_reauthentication() {
+            self.authenticate(client).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn call<T, Req>(client: &mut TypedClient<T>, version: usize, req: Req) -> crate::Result<Req::Response>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+        Req: ApiRequest + Send + 'static,
+{
+    let ready = ServiceExt::<CallReq<Req>>::ready_and(client).await?;
+    ready.call(CallReq::new(version, req)).await
+}
+
+/// Broker accepting PLAIN credentials of `user:secret` and expiring sessions after `lifetime_ms`.
+#[cfg(test)]
+fn plain_broker(lifetime_ms: i64, authentications: std::sync::Arc<std::sync::atomic::AtomicUsize>) -> tokio::net::UnixStream {
+    use std::sync::atomic::Ordering;
+    use crate::transport::mock;
+
+    mock::spawn(move |req| match req.header.api_key {
+        ApiKey::SaslHandshake => {
+            let mechanism = req.decode::<sasl_handshake::Request>().mechanism;
+            let error_code = if mechanism == "PLAIN" { 0 } else { KafkaCode::UnsupportedSaslMechanism.into() };
+            Some(req.respond::<sasl_handshake::Request>(&sasl_handshake::Response {
+                error_code,
+                mechanisms: vec!["PLAIN".to_string()],
+            }))
+        }
+        ApiKey::SaslAuthenticate => {
+            authentications.fetch_add(1, Ordering::SeqCst);
+            let res = if req.decode::<sasl_authenticate::Request>().auth_bytes == b"\0user\0secret"[..] {
+                sasl_authenticate::Response {
+                    session_lifetime_ms: Some(lifetime_ms),
+                    tags: TagBuffer::default().into(),
+                    ..Default::default()
+                }
+            } else {
+                sasl_authenticate::Response {
+                    error_code: KafkaCode::SaslAuthenticationFailed.into(),
+                    error_message: Some("Invalid username or password".to_string()),
+                    session_lifetime_ms: Some(0),
+                    tags: TagBuffer::default().into(),
+                    ..Default::default()
+                }
+            };
+            Some(req.respond::<sasl_authenticate::Request>(&res))
+        }
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn test_sasl_plain() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let versions = vec![(ApiKey::SaslHandshake, (0, 1)), (ApiKey::SaslAuthenticate, (0, 2))].into_iter().collect();
+    let config = |mechanism, password: &str| SaslConfig {
+        mechanism,
+        username: "user".to_string(),
+        password: password.to_string(),
+        ..Default::default()
+    };
+
+    let authentications = Arc::new(AtomicUsize::new(0));
+    let mut client = crate::transport::new(plain_broker(60_000, authentications.clone())).await;
+    let session = Session::new(config(SaslMechanism::Plain, "secret"), &versions).unwrap();
+    session.authenticate(&mut client).await.unwrap();
+    let deadline = session.reauthenticate_at.lock().unwrap().unwrap();
+    assert!(deadline > Instant::now() + Duration::from_secs(50) && deadline < Instant::now() + Duration::from_secs(58));
+
+    session.refresh(&mut client).await.unwrap();
+    assert_eq!(authentications.load(Ordering::SeqCst), 1);
+    *session.reauthenticate_at.lock().unwrap() = Some(Instant::now());
+    session.refresh(&mut client).await.unwrap();
+    assert_eq!(authentications.load(Ordering::SeqCst), 2);
+
+    let session = Session::new(config(SaslMechanism::Plain, "wrong"), &versions).unwrap();
+    let err = session.authenticate(&mut client).await.unwrap_err();
+    match err {
+        crate::Error::Sasl(SaslError::Failed(err)) => {
+            assert_eq!(err.code, KafkaCode::SaslAuthenticationFailed);
+            assert_eq!(err.message.as_deref(), Some("Invalid username or password"));
+        }
+        _ => panic!("Unexpected error {}", err),
+    }
+
+    let session = Session::new(config(SaslMechanism::ScramSha512, "secret"), &versions).unwrap();
+    let err = session.authenticate(&mut client).await.unwrap_err();
+    assert_eq!(err.to_string(), "SASL mechanism SCRAM-SHA-512 is not enabled by the broker, enabled mechanisms: PLAIN");
+
+    // Brokers which only support raw SASL tokens
+    let versions = vec![(ApiKey::SaslHandshake, (0, 0))].into_iter().collect();
+    assert!(Session::new(config(SaslMechanism::Plain, "secret"), &versions).is_err());
+}
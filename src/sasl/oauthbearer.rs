@@ -0,0 +1,227 @@
+//! OAUTHBEARER from RFC 7628, with tokens supplied by the application through a [TokenProvider].
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use futures::future::BoxFuture;
+
+use super::{Mechanism, SaslError};
+
+/// Separator of the key-value pairs in the client-first message.
+const KVSEP: char = '\x01';
+
+/// Bearer token and the extensions (KIP-342) sent along with it.
+#[derive(Clone)]
+pub struct OAuthToken {
+    pub value: String,
+    /// Connections are re-authenticated with a new token before this instant.
+    pub expires_at: SystemTime,
+    pub extensions: Vec<(String, String)>,
+}
+
+/// Failure of a [TokenProvider], reported as [SaslError::Token].
+pub type TokenError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Supplies bearer tokens, called before every authentication and re-authentication of a connection.
+///
+/// Implementations should cache tokens until they are close to expiry, since every connection asks for one.
+pub trait TokenProvider: Send + Sync {
+    fn token(&self) -> BoxFuture<'_, Result<OAuthToken, TokenError>>;
+}
+
+/// Provides unsigned JWS tokens (`"alg": "none"`), accepted by brokers configured with
+/// `OAuthBearerUnsecuredValidatorCallbackHandler`.
+///
+/// Only meant for tests and development clusters, without an identity service.
+pub struct UnsecuredJwsProvider {
+    pub principal: String,
+    pub lifetime: Duration,
+    pub scope: Vec<String>,
+}
+
+impl UnsecuredJwsProvider {
+    pub fn new(principal: &str, lifetime: Duration) -> Self {
+        Self {
+            principal: principal.to_string(),
+            lifetime,
+            scope: vec![],
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+impl TokenProvider for UnsecuredJwsProvider {
+    fn token(&self) -> BoxFuture<'_, Result<OAuthToken, TokenError>> {
+        let issued_at = SystemTime::now();
+        let expires_at = issued_at + self.lifetime;
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+
+        let mut claims = format!(
+            r#"{{"sub":{},"iat":{:.3},"exp":{:.3}"#,
+            json_string(&self.principal), secs(issued_at), secs(expires_at)
+        );
+        if !self.scope.is_empty() {
+            let scope = self.scope.iter().map(|s| json_string(s)).collect::<Vec<_>>();
+            claims.push_str(&format!(r#","scope":[{}]"#, scope.join(",")));
+        }
+        claims.push('}');
+
+        let value = format!("{}.{}.", BASE64URL.encode(r#"{"alg":"none"}"#), BASE64URL.encode(claims));
+        Box::pin(futures::future::ok(OAuthToken {
+            value,
+            expires_at,
+            extensions: vec![],
+        }))
+    }
+}
+
+pub(crate) struct OAuthBearer {
+    token: Option<OAuthToken>,
+    /// Set once the error challenge of the broker was answered with a lone separator, after which the broker fails the exchange.
+    rejected: bool,
+}
+
+impl OAuthBearer {
+    pub(crate) fn new(token: OAuthToken) -> Self {
+        Self { token: Some(token), rejected: false }
+    }
+
+    fn client_first(token: &OAuthToken) -> Vec<u8> {
+        let mut msg = format!("n,,{}auth=Bearer {}{}", KVSEP, token.value, KVSEP);
+        for (key, value) in &token.extensions {
+            msg.push_str(&format!("{}={}{}", key, value, KVSEP));
+        }
+        msg.push(KVSEP);
+        msg.into_bytes()
+    }
+}
+
+impl Mechanism for OAuthBearer {
+    fn step(&mut self, challenge: Option<&[u8]>) -> Result<Option<Vec<u8>>, SaslError> {
+        match challenge {
+            None => {
+                let token = self.token.take()
+                    .ok_or_else(|| SaslError::Protocol("Unexpected message in the OAUTHBEARER exchange".to_string()))?;
+                Ok(Some(Self::client_first(&token)))
+            }
+            Some([]) => Ok(None),
+            // The broker describes the error in a JSON challenge, the exchange must be completed before it fails it
+            Some(_) if !self.rejected => {
+                self.rejected = true;
+                Ok(Some(vec![KVSEP as u8]))
+            }
+            Some(challenge) => Err(SaslError::Protocol(format!(
+                "Token rejected by the broker: {}", String::from_utf8_lossy(challenge)
+            ))),
+        }
+    }
+}
+
+#[test]
+fn test_oauthbearer_messages() {
+    let token = OAuthToken {
+        value: "token".to_string(),
+        expires_at: SystemTime::now(),
+        extensions: vec![("traceId".to_string(), "123".to_string())],
+    };
+    let mut mechanism = OAuthBearer::new(token);
+    assert_eq!(mechanism.step(None).unwrap().unwrap(), b"n,,\x01auth=Bearer token\x01traceId=123\x01\x01");
+    assert!(mechanism.step(Some(b"")).unwrap().is_none());
+
+    let token = OAuthToken { value: "token".to_string(), expires_at: SystemTime::now(), extensions: vec![] };
+    let mut mechanism = OAuthBearer::new(token);
+    mechanism.step(None).unwrap();
+    assert_eq!(mechanism.step(Some(br#"{"status":"invalid_token"}"#)).unwrap().unwrap(), b"\x01");
+    assert!(mechanism.step(Some(br#"{"status":"invalid_token"}"#)).is_err());
+}
+
+#[tokio::test]
+async fn test_unsecured_jws() {
+    let provider = UnsecuredJwsProvider {
+        scope: vec!["read".to_string()],
+        ..UnsecuredJwsProvider::new("al\"ice", Duration::from_secs(60))
+    };
+    let token = provider.token().await.unwrap();
+    let parts = token.value.split('.').collect::<Vec<_>>();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(BASE64URL.decode(parts[0]).unwrap(), br#"{"alg":"none"}"#);
+    assert_eq!(parts[2], "");
+
+    let claims = String::from_utf8(BASE64URL.decode(parts[1]).unwrap()).unwrap();
+    assert!(claims.starts_with(r#"{"sub":"al\"ice","iat":"#));
+    assert!(claims.ends_with(r#","scope":["read"]}"#));
+    assert!(token.expires_at > SystemTime::now() + Duration::from_secs(59));
+}
+
+#[tokio::test]
+async fn test_oauthbearer_authentication() {
+    use std::sync::Arc;
+    use crate::config::{SaslConfig, SaslMechanism};
+    use crate::proto::{sasl_authenticate, sasl_handshake, ApiKey, TagBuffer};
+    use crate::transport::mock;
+    use crate::KafkaCode;
+
+    // Accepts tokens of `alice`, the session does not expire
+    let broker = mock::spawn(move |req| match req.header.api_key {
+        ApiKey::SaslHandshake => Some(req.respond::<sasl_handshake::Request>(&sasl_handshake::Response {
+            error_code: 0,
+            mechanisms: vec!["OAUTHBEARER".to_string()],
+        })),
+        ApiKey::SaslAuthenticate => {
+            let msg = req.decode::<sasl_authenticate::Request>().auth_bytes;
+            let msg = String::from_utf8(msg.to_vec()).unwrap();
+            let claims = msg.strip_prefix("n,,\x01auth=Bearer ")
+                .and_then(|token| token.split('.').nth(1))
+                .map(|claims| String::from_utf8(BASE64URL.decode(claims).unwrap()).unwrap());
+            let mut res = sasl_authenticate::Response {
+                session_lifetime_ms: Some(0),
+                tags: TagBuffer::default().into(),
+                ..Default::default()
+            };
+            match claims {
+                Some(claims) if claims.starts_with(r#"{"sub":"alice""#) => {}
+                Some(_) => res.auth_bytes = bytes::Bytes::from_static(br#"{"status":"invalid_token"}"#),
+                None => {
+                    res.error_code = KafkaCode::SaslAuthenticationFailed.into();
+                    res.error_message = Some("Authentication failed: invalid_token".to_string());
+                }
+            }
+            Some(req.respond::<sasl_authenticate::Request>(&res))
+        }
+        _ => None,
+    });
+    let mut client = crate::transport::new(broker).await;
+    let versions = vec![(ApiKey::SaslHandshake, (0, 1)), (ApiKey::SaslAuthenticate, (0, 2))].into_iter().collect();
+    let config = |principal: &str| SaslConfig {
+        mechanism: SaslMechanism::OAuthBearer,
+        token_provider: Some(Arc::new(UnsecuredJwsProvider::new(principal, Duration::from_secs(10)))),
+        ..Default::default()
+    };
+
+    let session = super::Session::new(config("alice"), &versions).unwrap();
+    session.authenticate(&mut client).await.unwrap();
+    // Re-authenticated before the token expires, even though the session does not
+    let deadline = session.reauthenticate_at.lock().unwrap().unwrap();
+    assert!(deadline < std::time::Instant::now() + Duration::from_secs(10));
+
+    let session = super::Session::new(config("mallory"), &versions).unwrap();
+    let err = session.authenticate(&mut client).await.unwrap_err();
+    assert_eq!(err.to_string(), "SASL authentication failed: SaslAuthenticationFailed (58): Authentication failed: invalid_token");
+
+    let config = SaslConfig { mechanism: SaslMechanism::OAuthBearer, ..Default::default() };
+    assert!(super::Session::new(config, &versions).is_err());
+}
@@ -0,0 +1,210 @@
+//! SCRAM-SHA-256 and SCRAM-SHA-512 from RFC 5802, without channel binding.
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256, Sha512};
+
+use super::{Mechanism, SaslError};
+
+/// Brokers refuse fewer iterations, a lower count from the server is treated as a downgrade attempt.
+const MIN_ITERATIONS: u32 = 4096;
+
+/// GS2 header of a client without channel binding support.
+const GS2_HEADER: &str = "n,,";
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Hash {
+    Sha256,
+    Sha512,
+}
+
+impl Hash {
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Hash::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Hash::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Hash::Sha256 => Sha256::digest(data).to_vec(),
+            Hash::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    fn salted_password(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        match self {
+            Hash::Sha256 => {
+                let mut res = vec![0; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut res);
+                res
+            }
+            Hash::Sha512 => {
+                let mut res = vec![0; 64];
+                pbkdf2::pbkdf2_hmac::<Sha512>(password, salt, iterations, &mut res);
+                res
+            }
+        }
+    }
+}
+
+enum State {
+    Initial,
+    /// Client-first message was sent, without the GS2 header.
+    ClientFirst { bare: String },
+    /// Client-final message was sent, the server must prove it knows the password.
+    ClientFinal { server_signature: Vec<u8> },
+    Done,
+}
+
+pub(crate) struct Scram {
+    hash: Hash,
+    username: String,
+    password: String,
+    nonce: String,
+    state: State,
+}
+
+impl Scram {
+    pub(crate) fn new(hash: Hash, username: &str, password: &str) -> Self {
+        let mut nonce = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        Self::with_nonce(hash, username, password, BASE64.encode(nonce))
+    }
+
+    fn with_nonce(hash: Hash, username: &str, password: &str, nonce: String) -> Self {
+        Self {
+            hash,
+            username: username.to_string(),
+            password: password.to_string(),
+            nonce,
+            state: State::Initial,
+        }
+    }
+
+    fn client_first(&mut self) -> Vec<u8> {
+        // `=` and `,` are the only characters to be escaped in a saslname
+        let username = self.username.replace('=', "=3D").replace(',', "=2C");
+        let bare = format!("n={},r={}", username, self.nonce);
+        let msg = format!("{}{}", GS2_HEADER, bare);
+        self.state = State::ClientFirst { bare };
+        msg.into_bytes()
+    }
+
+    fn client_final(&mut self, bare: &str, server_first: &[u8]) -> Result<Vec<u8>, SaslError> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| SaslError::Protocol("Server-first message is not valid UTF-8".to_string()))?;
+
+        let nonce = attribute(server_first, 'r')?;
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err(SaslError::Protocol("Server nonce does not extend the client nonce".to_string()));
+        }
+        let salt = BASE64.decode(attribute(server_first, 's')?)
+            .map_err(|_| SaslError::Protocol("Salt is not valid base64".to_string()))?;
+        let iterations = attribute(server_first, 'i')?.parse::<u32>()
+            .map_err(|_| SaslError::Protocol("Iteration count is not a number".to_string()))?;
+        if iterations < MIN_ITERATIONS {
+            return Err(SaslError::Protocol(format!(
+                "Iteration count {} is lower than the minimum {}", iterations, MIN_ITERATIONS
+            )));
+        }
+
+        let salted = self.hash.salted_password(self.password.as_bytes(), &salt, iterations);
+        let client_key = self.hash.hmac(&salted, b"Client Key");
+        let stored_key = self.hash.digest(&client_key);
+
+        let without_proof = format!("c={},r={}", BASE64.encode(GS2_HEADER), nonce);
+        let auth_message = format!("{},{},{}", bare, server_first, without_proof);
+
+        let client_signature = self.hash.hmac(&stored_key, auth_message.as_bytes());
+        let proof = client_key.iter().zip(client_signature).map(|(k, s)| k ^ s).collect::<Vec<_>>();
+
+        let server_key = self.hash.hmac(&salted, b"Server Key");
+        let server_signature = self.hash.hmac(&server_key, auth_message.as_bytes());
+        self.state = State::ClientFinal { server_signature };
+
+        Ok(format!("{},p={}", without_proof, BASE64.encode(proof)).into_bytes())
+    }
+
+    fn verify(server_signature: &[u8], server_final: &[u8]) -> Result<(), SaslError> {
+        let server_final = std::str::from_utf8(server_final)
+            .map_err(|_| SaslError::Protocol("Server-final message is not valid UTF-8".to_string()))?;
+        if let Ok(err) = attribute(server_final, 'e') {
+            return Err(SaslError::Protocol(format!("Server rejected the authentication: {}", err)));
+        }
+        let verifier = BASE64.decode(attribute(server_final, 'v')?)
+            .map_err(|_| SaslError::Protocol("Server signature is not valid base64".to_string()))?;
+        if verifier != server_signature {
+            return Err(SaslError::Protocol("Server signature does not match, the broker does not know the password".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Value of the `name=value` attribute in a comma separated SCRAM message.
+fn attribute(msg: &str, name: char) -> Result<&str, SaslError> {
+    msg.split(',')
+        .find_map(|attr| attr.strip_prefix(name)?.strip_prefix('='))
+        .ok_or_else(|| SaslError::Protocol(format!("Attribute `{}` is missing from `{}`", name, msg)))
+}
+
+impl Mechanism for Scram {
+    fn step(&mut self, challenge: Option<&[u8]>) -> Result<Option<Vec<u8>>, SaslError> {
+        match (std::mem::replace(&mut self.state, State::Done), challenge) {
+            (State::Initial, None) => Ok(Some(self.client_first())),
+            (State::ClientFirst { bare }, Some(server_first)) => self.client_final(&bare, server_first).map(Some),
+            (State::ClientFinal { server_signature }, Some(server_final)) => {
+                Self::verify(&server_signature, server_final).map(|_| None)
+            }
+            _ => Err(SaslError::Protocol("Unexpected message in the SCRAM exchange".to_string())),
+        }
+    }
+}
+
+#[test]
+fn test_scram_sha256() {
+    // Test vector of RFC 7677
+    let mut scram = Scram::with_nonce(Hash::Sha256, "user", "pencil", "rOprNGfwEbeRWgbNEkqO".to_string());
+
+    let first = scram.step(None).unwrap().unwrap();
+    assert_eq!(first, b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+
+    let server_first = b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    let last = scram.step(Some(server_first)).unwrap().unwrap();
+    assert_eq!(
+        String::from_utf8(last).unwrap(),
+        "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+    );
+
+    let server_final = b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+    assert!(scram.step(Some(server_final)).unwrap().is_none());
+}
+
+#[test]
+fn test_scram_rejects_server() {
+    let server_first = b"r=abcdefgh123,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    let mut scram = Scram::with_nonce(Hash::Sha512, "us,er=", "pencil", "abcdefgh".to_string());
+    assert_eq!(scram.step(None).unwrap().unwrap(), b"n,,n=us=2Cer=3D,r=abcdefgh");
+    scram.step(Some(server_first)).unwrap();
+    assert!(scram.step(Some(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")).is_err());
+
+    // Nonce not extending the client nonce
+    let mut scram = Scram::with_nonce(Hash::Sha256, "user", "pencil", "zzzz".to_string());
+    scram.step(None).unwrap();
+    assert!(scram.step(Some(server_first)).is_err());
+
+    // Iteration count below the minimum
+    let mut scram = Scram::with_nonce(Hash::Sha256, "user", "pencil", "abcdefgh".to_string());
+    scram.step(None).unwrap();
+    assert!(scram.step(Some(b"r=abcdefgh123,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=1")).is_err());
+}
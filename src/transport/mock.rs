@@ -0,0 +1,118 @@
+//! Broker stand-in for tests, answering requests over a socket pair since tests can't open TCP sockets.
+use std::io;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::future::BoxFuture;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use crate::proto::api_versions;
+use crate::proto::{ApiKey, ApiRequest, TagBuffer, WireRead, WireWrite, Wired};
+use super::{Connector, RequestHeader};
+
+pub(crate) struct Request {
+    pub(crate) header: RequestHeader,
+    body: Bytes,
+}
+
+impl Request {
+    /// Decodes the request, skipping the tagged fields of the header in flexible versions.
+    pub(crate) fn decode<R: ApiRequest>(&self) -> R {
+        let version = self.header.api_version as usize;
+        let mut body = self.body.clone();
+        let mut wire = WireRead { version, buffer: &mut body };
+        if R::request_header_version(version) >= 2 {
+            TagBuffer::from_wire(&mut wire).unwrap();
+        }
+        if version >= R::FLEXIBLE_VER {
+            R::from_wire_compact(&mut wire).unwrap()
+        } else {
+            R::from_wire(&mut wire).unwrap()
+        }
+    }
+
+    /// Encodes the response with the header matching the request version.
+    pub(crate) fn respond<R: ApiRequest>(&self, res: &R::Response) -> Bytes {
+        let version = self.header.api_version as usize;
+        let mut buf = BytesMut::new();
+        let mut wire = WireWrite { version, buffer: &mut buf };
+        if R::response_header_version(version) >= 1 {
+            TagBuffer::default().to_wire(&mut wire);
+        }
+        if version >= R::FLEXIBLE_VER {
+            res.to_wire_compact(&mut wire);
+        } else {
+            res.to_wire(&mut wire);
+        }
+        buf.freeze()
+    }
+}
+
+/// Answers `ApiVersions` as a broker supporting `versions`.
+pub(crate) fn api_versions(req: &Request, versions: &[(ApiKey, i16, i16)]) -> Bytes {
+    req.respond::<api_versions::Request>(&api_versions::Response::new(versions))
+}
+
+/// Spawns a broker answering requests with `handler`, the connection is closed once it returns `None`.
+pub(crate) fn spawn<F>(mut handler: F) -> UnixStream
+    where F: FnMut(Request) -> Option<Bytes> + Send + 'static
+{
+    spawn_delayed(move |req| handler(req).map(|res| (Duration::from_secs(0), res)))
+}
+
+/// Spawns a broker answering requests after the delay returned by `handler`, requests are read and answered in order.
+///
+/// Empty responses are not sent, like by a broker which lost the request.
+pub(crate) fn spawn_delayed<F>(mut handler: F) -> UnixStream
+    where F: FnMut(Request) -> Option<(Duration, Bytes)> + Send + 'static
+{
+    let (client, mut server) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let len = match server.read_i32().await {
+                Ok(len) => len as usize,
+                Err(_) => return,
+            };
+            let mut frame = vec![0u8; len];
+            if server.read_exact(&mut frame).await.is_err() {
+                return;
+            }
+            let mut body = Bytes::from(frame);
+            let header = RequestHeader::from_wire(&mut WireRead { version: 1, buffer: &mut body }).unwrap();
+            let correlation_id = header.correlation_id;
+
+            let (delay, res) = match handler(Request { header, body }) {
+                Some(res) => res,
+                None => return,
+            };
+            tokio::time::delay_for(delay).await;
+            if res.is_empty() {
+                continue;
+            }
+            let mut frame = BytesMut::with_capacity(8 + res.len());
+            frame.put_i32(4 + res.len() as i32);
+            frame.put_i32(correlation_id);
+            frame.put(res);
+            if server.write_all(&frame).await.is_err() {
+                return;
+            }
+        }
+    });
+    client
+}
+
+/// Connects to the brokers spawned for each address, refusing connections to addresses mapped to `None`.
+#[derive(Clone)]
+pub(crate) struct MockConnector<F>(pub(crate) F);
+
+impl<F> Connector for MockConnector<F>
+    where F: Fn(&str) -> Option<UnixStream> + Send + Sync + 'static
+{
+    type Stream = UnixStream;
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, io::Result<UnixStream>> {
+        let res = (self.0)(addr).ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, addr.to_string()));
+        Box::pin(futures::future::ready(res))
+    }
+}
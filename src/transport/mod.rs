@@ -8,6 +8,7 @@ use crate::proto::{Wired, WireWrite, ApiKey, WireRead, ApiRequest, TagBuffer};
 use tokio::io::Error;
 use std::ops::DerefMut;
 use tower::{Service, ServiceExt};
+use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use std::future::Future;
@@ -17,6 +18,16 @@ use futures::FutureExt;
 use byteorder::{BigEndian, ByteOrder};
 use crate::proto::api_versions::Request;
 
+mod stream;
+mod throttle;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(test)]
+pub(crate) mod mock;
+
+pub use stream::{Connector, Stream, TcpConnector};
+pub use throttle::{Throttle, ThrottleMetrics};
+
 
 #[derive(Default, Clone, Wired)]
 pub struct RequestHeader {
@@ -38,7 +49,7 @@ pub struct ResponseHeader {
 #[derive(Default, Clone)]
 pub struct RawRequest {
     header: RequestHeader,
-    flexible: bool,
+    header_version: usize,
     data: Bytes,
 }
 
@@ -47,16 +58,36 @@ pub struct RawResponse {
     data: Bytes,
 }
 
-pub struct Codec {}
+/// Default limit of the response size, matching `receive.message.max.bytes` of librdkafka.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 100_000_000;
+
+/// Length-delimited framing of requests and responses.
+pub struct Codec {
+    max_response_size: usize,
+}
+
+impl Codec {
+    /// Responses declaring a larger size than `max_response_size` fail the connection,
+    /// instead of buffering arbitrary amounts of data.
+    pub fn new(max_response_size: usize) -> Self {
+        Self { max_response_size }
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_RESPONSE_SIZE)
+    }
+}
 
 impl Encoder<RawRequest> for Codec {
-    type Error = std::io::Error;
+    type Error = crate::Error;
 
     fn encode(&mut self, item: RawRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
         dst.put_i32(0);
 
         let mut wire = WireWrite {
-            version: if item.flexible { 2 } else { 1 },
+            version: item.header_version,
             buffer: dst,
         };
         item.header.to_wire(&mut wire);
@@ -70,52 +101,181 @@ impl Encoder<RawRequest> for Codec {
 
 impl Decoder for Codec {
     type Item = RawResponse;
-    type Error = std::io::Error;
+    type Error = crate::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        println!("remaining: {:?}", src.len());
-        if src.len() == 0 {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = BigEndian::read_i32(src);
+        // Every response starts with the correlation id
+        if len < 4 || len as usize > self.max_response_size {
+            return Err(crate::Error::Framing(
+                format!("Invalid response size {}, the maximum is {}", len, self.max_response_size),
+            ));
+        }
+        let len = len as usize;
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
             return Ok(None);
         }
-        let len = src.get_i32();
-        let corr = src.get_i32();
-        let data = src.split_to((len - 4) as usize);
+        src.advance(4);
+        let mut data = src.split_to(len);
+        let corr = data.get_i32();
         Ok(Some(RawResponse {
             corr_id: corr,
-            data: data.into(),
+            data: data.freeze(),
         }))
     }
 }
 
+/// Correlation ids of a connection, shared by the [Tagger] and the [TypedClient] assigning them.
+#[derive(Default)]
+struct Tags {
+    last: i32,
+    /// Requests dropped by their callers before the response arrived, like those which timed out.
+    abandoned: HashSet<i32>,
+}
+
+impl Tags {
+    /// Next non-negative id, skipping abandoned ids whose late responses could otherwise be routed to a new request.
+    fn next(&mut self) -> i32 {
+        loop {
+            self.last = self.last.wrapping_add(1) & i32::MAX;
+            if !self.abandoned.contains(&self.last) {
+                return self.last;
+            }
+        }
+    }
+}
+
+/// Marks the request abandoned unless its response arrived before it was dropped.
+struct InFlight {
+    id: i32,
+    tags: Arc<std::sync::Mutex<Tags>>,
+    done: bool,
+}
+
+impl InFlight {
+    fn finish(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if !self.done {
+            self.tags.lock().unwrap().abandoned.insert(self.id);
+        }
+    }
+}
+
 pub struct Tagger {
-    counter: i32
+    tags: Arc<std::sync::Mutex<Tags>>,
 }
 
 impl tokio_tower::multiplex::TagStore<RawRequest, RawResponse> for Tagger {
     type Tag = i32;
 
-    fn assign_tag(mut self: Pin<&mut Self>, r: &mut RawRequest) -> Self::Tag {
-        self.deref_mut().counter += 1;
-        r.header.correlation_id = self.counter;
-        self.counter
+    fn assign_tag(self: Pin<&mut Self>, r: &mut RawRequest) -> Self::Tag {
+        r.header.correlation_id
     }
 
+    /// Responses of abandoned requests are still matched, and discarded since nobody waits for them.
     fn finish_tag(self: Pin<&mut Self>, r: &RawResponse) -> Self::Tag {
+        self.tags.lock().unwrap().abandoned.remove(&r.corr_id);
         r.corr_id
     }
 }
 
 
-type MessageTransport<T> = Framed<T, Codec>;
+/// Cause of a broken connection, kept to fail the requests in flight with it.
+#[derive(Clone)]
+enum Failure {
+    Io(std::io::ErrorKind, String),
+    Framing(String),
+}
+
+/// Framed connection recording the error which broke it, since the multiplexer reports it to the requests
+/// in flight only as a dropped client.
+pub struct Monitored<T> {
+    framed: Pin<Box<Framed<T, Codec>>>,
+    failure: Arc<std::sync::Mutex<Option<Failure>>>,
+}
+
+impl<T> Monitored<T> {
+    fn record<R>(&self, res: Result<R, crate::Error>) -> Result<R, crate::Error> {
+        let failure = match &res {
+            Err(crate::Error::Io(e)) => Failure::Io(e.kind(), e.to_string()),
+            Err(crate::Error::Framing(msg)) => Failure::Framing(msg.clone()),
+            _ => return res,
+        };
+        self.failure.lock().unwrap().get_or_insert(failure);
+        res
+    }
+}
+
+impl<T> futures::Stream for Monitored<T>
+    where T: AsyncRead + AsyncWrite
+{
+    type Item = Result<RawResponse, crate::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let res = self.framed.as_mut().poll_next(cx);
+        res.map(|res| res.map(|res| self.record(res)))
+    }
+}
+
+impl<T> futures::Sink<RawRequest> for Monitored<T>
+    where T: AsyncRead + AsyncWrite
+{
+    type Error = crate::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let res = self.framed.as_mut().poll_ready(cx);
+        res.map(|res| self.record(res))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: RawRequest) -> Result<(), Self::Error> {
+        let res = self.framed.as_mut().start_send(item);
+        self.record(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let res = self.framed.as_mut().poll_flush(cx);
+        res.map(|res| self.record(res))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let res = self.framed.as_mut().poll_close(cx);
+        res.map(|res| self.record(res))
+    }
+}
 
 type MultiplexTransport<T> = tokio_tower::multiplex::MultiplexTransport<
-    MessageTransport<T>,
+    Monitored<T>,
     Tagger
 >;
 
 type TowerError<T> = tokio_tower::Error<MultiplexTransport<T>, RawRequest>;
 
-type RawClient<T> = tokio_tower::multiplex::Client<MultiplexTransport<T>, TowerError<T>, RawRequest>;
+type RawClient<T> = tokio_tower::multiplex::Client<MultiplexTransport<T>, crate::Error, RawRequest>;
+
+impl<T> From<TowerError<T>> for crate::Error
+    where T: AsyncRead + AsyncWrite
+{
+    fn from(err: TowerError<T>) -> Self {
+        match err {
+            tokio_tower::Error::BrokenTransportSend(err) => err,
+            tokio_tower::Error::BrokenTransportRecv(Some(err)) => err,
+            tokio_tower::Error::BrokenTransportRecv(None) | tokio_tower::Error::ClientDropped => crate::Error::Disconnected,
+            tokio_tower::Error::TransportFull => {
+                crate::Error::Io(std::io::Error::other("Too many requests in flight"))
+            }
+            tokio_tower::Error::Desynchronized => crate::Error::Framing("Response with an unknown correlation id".to_string()),
+        }
+    }
+}
 
 
 pub struct CallReq<Req> {
@@ -132,9 +292,62 @@ impl<Req> CallReq<Req> {
     }
 }
 
-pub struct TypedClient<T>(RawClient<T>)
+pub struct TypedClient<T>
     where
-        T: AsyncRead + AsyncWrite + 'static + Send;
+        T: AsyncRead + AsyncWrite + 'static + Send
+{
+    raw: RawClient<T>,
+    failure: Arc<std::sync::Mutex<Option<Failure>>>,
+    tags: Arc<std::sync::Mutex<Tags>>,
+    client_id: String,
+    throttle: Arc<Throttle>,
+    /// Wakes the client up once the throttle time requested by the broker elapsed.
+    throttled: Option<tokio::time::Delay>,
+}
+
+impl<T> TypedClient<T>
+    where
+        T: AsyncRead + AsyncWrite + 'static + Send
+{
+    /// Sets the client id sent in the header of every request, instead of [DEFAULT_CLIENT_ID].
+    ///
+    /// [DEFAULT_CLIENT_ID]: crate::config::DEFAULT_CLIENT_ID
+    pub fn set_client_id(&mut self, client_id: String) {
+        self.client_id = client_id;
+    }
+
+    /// Throttling requested by the broker in responses to this client.
+    pub fn throttle(&self) -> &Arc<Throttle> {
+        &self.throttle
+    }
+
+    /// Shares the throttling of another client to the same broker, like the one this client replaces.
+    pub fn set_throttle(&mut self, throttle: Arc<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    /// Holds back requests while the connection is throttled.
+    fn poll_throttle(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while let Some(until) = self.throttle.until() {
+            let delay = self.throttled.get_or_insert_with(|| tokio::time::delay_until(until.into()));
+            if delay.deadline() != until.into() {
+                delay.reset(until.into());
+            }
+            futures::ready!(Pin::new(delay).poll(cx));
+        }
+        self.throttled = None;
+        Poll::Ready(())
+    }
+
+    /// Replaces a bare disconnect with the error which broke the connection.
+    fn cause(failure: &std::sync::Mutex<Option<Failure>>, err: crate::Error) -> crate::Error {
+        match (err, failure.lock().unwrap().as_ref()) {
+            (crate::Error::Disconnected, Some(Failure::Io(kind, msg))) => std::io::Error::new(*kind, msg.clone()).into(),
+            (crate::Error::Disconnected, Some(Failure::Framing(msg))) => crate::Error::Framing(msg.clone()),
+            (err, _) => err,
+        }
+    }
+}
 
 impl<Req, T> tower::Service<CallReq<Req>> for TypedClient<T>
     where
@@ -142,23 +355,27 @@ impl<Req, T> tower::Service<CallReq<Req>> for TypedClient<T>
         Req: ApiRequest + Send + 'static,
 {
     type Response = Req::Response;
-    type Error = TowerError<T>;
+    type Error = crate::Error;
     type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>> + Send>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.0.poll_ready(cx)
+        futures::ready!(self.poll_throttle(cx));
+        let failure = &self.failure;
+        self.raw.poll_ready(cx).map_err(|e| Self::cause(failure, e))
     }
 
     fn call(&mut self, req: CallReq<Req>) -> Self::Future {
+        if let Err(err) = req.req.validate() {
+            return async move { Err(err) }.boxed();
+        }
         let mut buf = bytes::BytesMut::new();
-        let ver = req.api_ver as usize;
+        let ver = req.api_ver;
 
         let mut wire = WireWrite {
             version: req.api_ver as _,
             buffer: &mut buf,
         };
-        let flexible = req.api_ver as usize >= Req::FLEXIBLE_VER;
-        if flexible {
+        if req.api_ver >= Req::FLEXIBLE_VER {
             req.req.to_wire_compact(&mut wire);
         } else {
             req.req.to_wire(&mut wire);
@@ -167,29 +384,40 @@ impl<Req, T> tower::Service<CallReq<Req>> for TypedClient<T>
             header: RequestHeader {
                 api_version: req.api_ver as _,
                 api_key: Req::API_KEY,
-                correlation_id: 0,
-                client_id: Some("hello".to_string()),
-                tag_buffer: TagBuffer {}.into(),
+                correlation_id: self.tags.lock().unwrap().next(),
+                client_id: Some(self.client_id.clone()),
+                tag_buffer: TagBuffer::default().into(),
             },
-            flexible,
+            header_version: Req::request_header_version(ver),
             data: buf.freeze(),
         };
 
-        let fut = self.0.call(raw);
+        let in_flight = InFlight {
+            id: raw.header.correlation_id,
+            tags: self.tags.clone(),
+            done: false,
+        };
+        let fut = self.raw.call(raw);
+        let failure = self.failure.clone();
+        let throttle = self.throttle.clone();
         async move {
-            let mut res = fut.await.unwrap();
+            let res = fut.await;
+            in_flight.finish();
+            let mut res = res.map_err(|e| Self::cause(&failure, e))?;
 
             let mut read = WireRead {
                 buffer: &mut res.data,
                 version: req.api_ver as _,
             };
 
-            Ok(if flexible {
-                TagBuffer::from_wire(&mut read).unwrap();
-                Req::Response::from_wire_compact(&mut read).unwrap()
-            } else {
-                Req::Response::from_wire(&mut read).unwrap()
-            })
+            if Req::response_header_version(ver) >= 1 {
+                TagBuffer::from_wire(&mut read)?;
+            }
+            let res = Req::decode_response(&mut read)?;
+            if let Some(time) = Req::throttle_time(ver, &res) {
+                throttle.record(Req::API_KEY, time);
+            }
+            Ok(res)
         }.boxed()
     }
 }
@@ -197,15 +425,32 @@ impl<Req, T> tower::Service<CallReq<Req>> for TypedClient<T>
 pub async fn new<T>(io: T) -> TypedClient<T>
     where T: AsyncRead + AsyncWrite + Send + 'static
 {
+    with_codec(io, Codec::default()).await
+}
+
+pub async fn with_codec<T>(io: T, codec: Codec) -> TypedClient<T>
+    where T: AsyncRead + AsyncWrite + Send + 'static
+{
+    let tags = Arc::new(std::sync::Mutex::new(Tags::default()));
     let tagger = Tagger {
-        counter: 1
+        tags: tags.clone(),
     };
 
-    let msg_transport = MessageTransport::new(io, Codec {});
+    let failure = Arc::new(std::sync::Mutex::new(None));
+    let msg_transport = Monitored {
+        framed: Box::pin(Framed::new(io, codec)),
+        failure: failure.clone(),
+    };
     let t = MultiplexTransport::new(msg_transport, tagger);
 
-    let client = TypedClient(RawClient::new(t));
-    client
+    TypedClient {
+        raw: RawClient::new(t),
+        failure,
+        tags,
+        client_id: crate::config::DEFAULT_CLIENT_ID.to_string(),
+        throttle: Default::default(),
+        throttled: None,
+    }
 }
 
 impl<T> tower::Service<()> for TypedClient<T>
@@ -213,14 +458,118 @@ impl<T> tower::Service<()> for TypedClient<T>
         T: AsyncRead + AsyncWrite + 'static + Send,
 {
     type Response = ();
-    type Error = TowerError<T>;
+    type Error = crate::Error;
     type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.0.poll_ready(cx)
+        futures::ready!(self.poll_throttle(cx));
+        let failure = &self.failure;
+        self.raw.poll_ready(cx).map_err(|e| Self::cause(failure, e))
     }
 
     fn call(&mut self, req: ()) -> Self::Future {
         futures::future::ok(())
     }
 }
+
+#[test]
+fn test_codec_framing() {
+    let mut frames = BytesMut::new();
+    for (corr, body) in &[(1, &b"first"[..]), (2, &b""[..]), (3, &b"third"[..])] {
+        frames.put_i32(4 + body.len() as i32);
+        frames.put_i32(*corr);
+        frames.put_slice(body);
+    }
+
+    // Deliver the frames one byte at a time
+    let mut codec = Codec::default();
+    let mut src = BytesMut::new();
+    let mut decoded = vec![];
+    for b in frames.iter() {
+        src.put_u8(*b);
+        while let Some(res) = codec.decode(&mut src).unwrap() {
+            decoded.push((res.corr_id, res.data));
+        }
+    }
+    assert_eq!(decoded, vec![(1, Bytes::from_static(b"first")), (2, Bytes::new()), (3, Bytes::from_static(b"third"))]);
+
+    // Deliver all frames in one read
+    let mut src = frames.clone();
+    let corrs = std::iter::from_fn(|| codec.decode(&mut src).unwrap()).map(|r| r.corr_id).collect::<Vec<_>>();
+    assert_eq!(corrs, vec![1, 2, 3]);
+    assert!(src.is_empty());
+
+    let mut codec = Codec::new(8);
+    let mut src = frames.clone();
+    assert!(codec.decode(&mut src).is_err());
+}
+
+/// Sends `ApiVersions` v0.
+#[cfg(test)]
+async fn test_call<T>(client: &mut TypedClient<T>) -> crate::Result<crate::proto::api_versions::Response>
+    where T: AsyncRead + AsyncWrite + Send + 'static
+{
+    let req = crate::proto::api_versions::Request {
+        client_software_name: None,
+        client_software_version: None,
+        tags: None,
+    };
+    let ready = ServiceExt::<CallReq<crate::proto::api_versions::Request>>::ready_and(client).await?;
+    ready.call(CallReq::new(0, req)).await
+}
+
+#[tokio::test]
+async fn test_transport_errors() {
+    use crate::proto::ApiKey;
+
+    // Truncated response, the connection stays usable
+    let mut first = true;
+    let io = mock::spawn(move |req| Some(if std::mem::take(&mut first) {
+        Bytes::from_static(&[0, 0, 0, 0, 1])
+    } else {
+        mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3)])
+    }));
+    let mut client = new(io).await;
+    match test_call(&mut client).await {
+        Err(crate::Error::Decode(_)) => {}
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert_eq!(test_call(&mut client).await.unwrap().api_keys.len(), 1);
+
+    // Response larger than the limit of the codec
+    let io = mock::spawn(|req| Some(mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3), (ApiKey::Metadata, 0, 9)])));
+    let mut client = with_codec(io, Codec::new(16)).await;
+    match test_call(&mut client).await {
+        Err(crate::Error::Framing(msg)) => assert!(msg.contains("16"), "{}", msg),
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    // Connection closed by the broker
+    let mut client = new(mock::spawn(|_| None)).await;
+    match test_call(&mut client).await {
+        Err(crate::Error::Disconnected) | Err(crate::Error::Io(_)) => {}
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn test_abandoned_requests() {
+    use std::time::Duration;
+    use crate::proto::ApiKey;
+
+    let mut tags = Tags { last: i32::MAX - 1, abandoned: vec![i32::MAX, 0].into_iter().collect() };
+    assert_eq!(tags.next(), 1);
+
+    // The first response arrives after its caller gave up, and before the response of the second request
+    let mut first = true;
+    let io = mock::spawn_delayed(move |req| {
+        let delay = if std::mem::take(&mut first) { 100 } else { 0 };
+        Some((Duration::from_millis(delay), mock::api_versions(&req, &[(ApiKey::ApiVersions, 0, 3)])))
+    });
+    let mut client = new(io).await;
+    assert!(tokio::time::timeout(Duration::from_millis(10), test_call(&mut client)).await.is_err());
+    assert_eq!(client.tags.lock().unwrap().abandoned.len(), 1);
+
+    assert_eq!(test_call(&mut client).await.unwrap().api_keys.len(), 1);
+    assert!(client.tags.lock().unwrap().abandoned.is_empty());
+}
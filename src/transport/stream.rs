@@ -0,0 +1,117 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::config::{Config, SecurityConfig};
+
+/// Connection to a broker, either plain or wrapped in TLS depending on [SecurityConfig].
+pub enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    /// Connects to `addr` in the `host:port` form with the socket options of `config`,
+    /// and performs the TLS handshake when configured.
+    pub async fn connect(addr: &str, config: &Config) -> io::Result<Stream> {
+        let io = TcpStream::connect(addr).await?;
+        io.set_nodelay(config.socket.nodelay)?;
+        io.set_keepalive(config.socket.keepalive)?;
+        if let Some(size) = config.socket.send_buffer_size {
+            io.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = config.socket.receive_buffer_size {
+            io.set_recv_buffer_size(size)?;
+        }
+
+        if !config.security.uses_tls() {
+            return Ok(Stream::Plain(io));
+        }
+        #[cfg(feature = "tls")]
+        {
+            let host = addr.rsplit_once(':').map(|(host, _)| host).unwrap_or(addr);
+            // IPv6 literals are enclosed in brackets
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let io = super::tls::connect(io, host, &config.tls).await?;
+            Ok(Stream::Tls(Box::new(io)))
+        }
+        #[cfg(not(feature = "tls"))]
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SSL requires rafka to be built with the `tls` feature",
+        ))
+    }
+}
+
+/// Opens connections to brokers, so the client can run over transports other than TCP,
+/// like Unix domain sockets or SOCKS tunnels.
+pub trait Connector: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Send + 'static;
+
+    /// Connects to the broker at `addr`, in the `host:port` form.
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, io::Result<Self::Stream>>;
+}
+
+impl<C: Connector> Connector for Arc<C> {
+    type Stream = C::Stream;
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, io::Result<Self::Stream>> {
+        C::connect(self, addr)
+    }
+}
+
+/// Connects over TCP, performing the TLS handshake when the config requires it.
+#[derive(Debug, Clone, Default)]
+pub struct TcpConnector {
+    pub config: Config,
+}
+
+impl Connector for TcpConnector {
+    type Stream = Stream;
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, io::Result<Stream>> {
+        Box::pin(Stream::connect(addr, &self.config))
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(io) => Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(io) => Pin::new(io).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(io) => Pin::new(io).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(io) => Pin::new(io).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(io) => Pin::new(io).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(io) => Pin::new(io).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(io) => Pin::new(io).poll_shutdown(cx),
+        }
+    }
+}
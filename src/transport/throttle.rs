@@ -0,0 +1,86 @@
+//! Client-side throttling of connections whose requests exceeded a broker quota (KIP-219).
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::proto::ApiKey;
+
+/// Throttle times received over a connection, holding back requests until the last one elapsed.
+///
+/// Shared by the transports of a [Client](crate::client::Client), so it outlives reconnections.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    state: Mutex<(Option<Instant>, ThrottleMetrics)>,
+}
+
+/// Counters of the responses which asked the client to back off.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThrottleMetrics {
+    /// Responses carrying a non-zero throttle time.
+    pub throttled_responses: u64,
+    pub throttle_time_total: Duration,
+    pub throttle_time_max: Duration,
+    /// Total throttle time by the api of the throttled responses, quotas apply to produce and fetch separately.
+    pub throttle_time_by_api: BTreeMap<ApiKey, Duration>,
+}
+
+impl Throttle {
+    pub(crate) fn record(&self, api_key: ApiKey, time: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let (until, metrics) = &mut *state;
+        let end = Instant::now() + time;
+        *until = Some(until.map_or(end, |until| until.max(end)));
+
+        metrics.throttled_responses += 1;
+        metrics.throttle_time_total += time;
+        metrics.throttle_time_max = metrics.throttle_time_max.max(time);
+        *metrics.throttle_time_by_api.entry(api_key).or_default() += time;
+    }
+
+    /// Instant until which requests are held back, `None` when the connection is not throttled.
+    pub fn until(&self) -> Option<Instant> {
+        self.state.lock().unwrap().0.filter(|until| *until > Instant::now())
+    }
+
+    pub fn metrics(&self) -> ThrottleMetrics {
+        self.state.lock().unwrap().1.clone()
+    }
+}
+
+#[test]
+fn test_throttle_deadline() {
+    let throttle = Throttle::default();
+    assert_eq!(throttle.until(), None);
+
+    let start = Instant::now();
+    throttle.record(ApiKey::Fetch, Duration::from_secs(10));
+    let until = throttle.until().unwrap();
+    assert!(until >= start + Duration::from_secs(10));
+
+    // Shorter throttles received meanwhile keep the later deadline
+    throttle.record(ApiKey::Produce, Duration::from_secs(1));
+    assert_eq!(throttle.until(), Some(until));
+    throttle.record(ApiKey::Produce, Duration::from_secs(20));
+    assert!(throttle.until().unwrap() >= start + Duration::from_secs(20));
+
+    // Elapsed deadlines no longer hold back requests
+    let throttle = Throttle::default();
+    throttle.record(ApiKey::Fetch, Duration::from_millis(0));
+    assert_eq!(throttle.until(), None);
+}
+
+#[test]
+fn test_throttle_metrics() {
+    let throttle = Throttle::default();
+    assert_eq!(throttle.metrics(), ThrottleMetrics::default());
+
+    throttle.record(ApiKey::Fetch, Duration::from_millis(100));
+    throttle.record(ApiKey::Produce, Duration::from_millis(300));
+    throttle.record(ApiKey::Fetch, Duration::from_millis(50));
+    let metrics = throttle.metrics();
+    assert_eq!(metrics.throttled_responses, 3);
+    assert_eq!(metrics.throttle_time_total, Duration::from_millis(450));
+    assert_eq!(metrics.throttle_time_max, Duration::from_millis(300));
+    assert_eq!(metrics.throttle_time_by_api.get(&ApiKey::Fetch), Some(&Duration::from_millis(150)));
+    assert_eq!(metrics.throttle_time_by_api.get(&ApiKey::Produce), Some(&Duration::from_millis(300)));
+}
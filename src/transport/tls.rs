@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rustls::internal::pemfile;
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::config::TlsConfig;
+
+/// Name used for SNI when the broker is addressed by IP and hostname verification is disabled.
+const PLACEHOLDER_NAME: &str = "kafka";
+
+/// Signature algorithms accepted in certificate chains, the same set rustls accepts by default.
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Verifies the certificate chain against the trusted roots, but accepts any server name.
+struct SkipHostnameVerifier;
+
+impl ServerCertVerifier for SkipHostnameVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let (end, chain) = presented_certs.split_first().ok_or(TLSError::NoCertificatesPresented)?;
+        let cert = webpki::EndEntityCert::from(&end.0).map_err(TLSError::WebPKIError)?;
+        let chain = chain.iter().map(|c| c.0.as_ref()).collect::<Vec<_>>();
+        let anchors = roots.roots.iter().map(|r| r.to_trust_anchor()).collect::<Vec<_>>();
+        let now = webpki::Time::try_from(SystemTime::now()).map_err(|_| TLSError::FailedToGetCurrentTime)?;
+
+        cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS, &webpki::TLSServerTrustAnchors(&anchors), &chain, now)
+            .map_err(TLSError::WebPKIError)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn open(path: &std::path::Path) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| io::Error::new(e.kind(), format!("Opening {}: {}", path.display(), e)))
+}
+
+pub(crate) fn client_config(config: &TlsConfig) -> io::Result<ClientConfig> {
+    let mut res = ClientConfig::new();
+
+    match &config.ca_location {
+        Some(path) => {
+            let (valid, _) = res.root_store.add_pem_file(&mut open(path)?)
+                .map_err(|_| invalid(format!("{} is not a valid PEM file", path.display())))?;
+            if valid == 0 {
+                return Err(invalid(format!("{} contains no CA certificates", path.display())));
+            }
+        }
+        None => res.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+    }
+
+    match (&config.certificate_location, &config.key_location) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = pemfile::certs(&mut open(cert_path)?)
+                .map_err(|_| invalid(format!("{} is not a valid PEM file", cert_path.display())))?;
+            let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?).unwrap_or_default();
+            if keys.is_empty() {
+                keys = pemfile::rsa_private_keys(&mut open(key_path)?).unwrap_or_default();
+            }
+            let key = keys.into_iter().next()
+                .ok_or_else(|| invalid(format!("{} contains no private key", key_path.display())))?;
+            res.set_single_client_cert(certs, key).map_err(|e| invalid(e.to_string()))?;
+        }
+        (None, None) => {}
+        _ => return Err(invalid("Client authentication requires both a certificate and a key".to_string())),
+    }
+
+    if config.skip_hostname_verification {
+        res.dangerous().set_certificate_verifier(Arc::new(SkipHostnameVerifier));
+    }
+    Ok(res)
+}
+
+/// Performs the TLS handshake over an established connection to `host`.
+pub(crate) async fn connect<T>(io: T, host: &str, config: &TlsConfig) -> io::Result<TlsStream<T>>
+    where T: AsyncRead + AsyncWrite + Unpin
+{
+    let connector = TlsConnector::from(Arc::new(client_config(config)?));
+
+    let name = config.server_name.as_deref().unwrap_or(host);
+    let name = match webpki::DNSNameRef::try_from_ascii_str(name) {
+        Ok(name) => name,
+        Err(_) if config.skip_hostname_verification => webpki::DNSNameRef::try_from_ascii_str(PLACEHOLDER_NAME).unwrap(),
+        Err(_) => return Err(invalid(format!("`{}` is not a valid DNS name, set `server_name` to verify the broker", name))),
+    };
+    connector.connect(name, io).await
+}
+
+#[cfg(test)]
+use std::path::PathBuf;
+
+/// CA writing its certificates to a temporary directory.
+#[cfg(test)]
+struct TestPki {
+    dir: PathBuf,
+    ca: rcgen::Certificate,
+}
+
+#[cfg(test)]
+impl TestPki {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rafka-tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut params = rcgen::CertificateParams::new(vec![]);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        Self { dir, ca }
+    }
+
+    /// Issues a certificate for `names`, returning the DER chain and key.
+    fn issue(&self, file: &str, names: &[&str]) -> (Vec<Certificate>, rustls::PrivateKey) {
+        let cert = rcgen::generate_simple_self_signed(names.iter().map(|n| n.to_string()).collect::<Vec<_>>()).unwrap();
+        let der = cert.serialize_der_with_signer(&self.ca).unwrap();
+        let pem = cert.serialize_pem_with_signer(&self.ca).unwrap();
+        std::fs::write(self.dir.join(format!("{}.pem", file)), pem).unwrap();
+        std::fs::write(self.dir.join(format!("{}.key", file)), cert.serialize_private_key_pem()).unwrap();
+        (vec![Certificate(der)], rustls::PrivateKey(cert.serialize_private_key_der()))
+    }
+
+    fn acceptor(&self, names: &[&str], client_auth: bool) -> tokio_rustls::TlsAcceptor {
+        let (chain, key) = self.issue("server", names);
+        let verifier = if client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add(&Certificate(self.ca.serialize_der().unwrap())).unwrap();
+            rustls::AllowAnyAuthenticatedClient::new(roots)
+        } else {
+            rustls::NoClientAuth::new()
+        };
+        let mut config = rustls::ServerConfig::new(verifier);
+        config.set_single_cert(chain, key).unwrap();
+        tokio_rustls::TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+/// Connects to a server which echoes 5 bytes back, over a socket pair instead of TCP.
+#[cfg(test)]
+async fn echo(acceptor: &tokio_rustls::TlsAcceptor, host: &str, config: &TlsConfig) -> io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (client, server) = tokio::net::UnixStream::pair()?;
+    let acceptor = acceptor.clone();
+    tokio::spawn(async move {
+        if let Ok(mut io) = acceptor.accept(server).await {
+            let mut buf = [0u8; 5];
+            if io.read_exact(&mut buf).await.is_ok() {
+                let _ = io.write_all(&buf).await;
+                let _ = io.flush().await;
+            }
+        }
+    });
+
+    let mut io = connect(client, host, config).await?;
+    io.write_all(b"hello").await?;
+    let mut buf = vec![0u8; 5];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+#[tokio::test]
+async fn test_tls_verification() {
+    let pki = TestPki::new("verify");
+    let acceptor = pki.acceptor(&["broker.test"], false);
+    let config = TlsConfig {
+        ca_location: Some(pki.dir.join("ca.pem")),
+        ..Default::default()
+    };
+
+    assert_eq!(echo(&acceptor, "broker.test", &config).await.unwrap(), b"hello");
+    // Certificate does not match the host
+    assert!(echo(&acceptor, "other.test", &config).await.is_err());
+    // Not trusted by the default roots
+    assert!(echo(&acceptor, "broker.test", &TlsConfig::default()).await.is_err());
+
+    let config = TlsConfig {
+        server_name: Some("broker.test".to_string()),
+        ..config
+    };
+    assert_eq!(echo(&acceptor, "127.0.0.1", &config).await.unwrap(), b"hello");
+
+    let config = TlsConfig {
+        server_name: None,
+        skip_hostname_verification: true,
+        ..config
+    };
+    assert_eq!(echo(&acceptor, "127.0.0.1", &config).await.unwrap(), b"hello");
+    assert_eq!(echo(&acceptor, "other.test", &config).await.unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn test_tls_client_auth() {
+    let pki = TestPki::new("mtls");
+    let acceptor = pki.acceptor(&["broker.test"], true);
+    pki.issue("client", &["client.test"]);
+
+    let config = TlsConfig {
+        ca_location: Some(pki.dir.join("ca.pem")),
+        ..Default::default()
+    };
+    assert!(echo(&acceptor, "broker.test", &config).await.is_err());
+
+    let config = TlsConfig {
+        certificate_location: Some(pki.dir.join("client.pem")),
+        key_location: Some(pki.dir.join("client.key")),
+        ..config
+    };
+    assert_eq!(echo(&acceptor, "broker.test", &config).await.unwrap(), b"hello");
+}